@@ -26,6 +26,7 @@
 #include "timer.hh"
 #include "utils.hh"
 #include "digraph.hh"
+#include "orbit.hh"
 
 const std::string DIRECTORY_PATH    = "/tmp/gql/";
 const std::string PIPE_NAME         = "my_pipe";
@@ -33,6 +34,8 @@ const std::string RESULT_PATH       = "result";
 const std::string USER_RULES_PATH   = "user_rules";
 const std::string SRC_PATH          = "src/";
 const std::string TARGET_GRAPH_FILE = "/tmp/gql/src_graph.txt";
+const std::string AUTOMORPHISMS_FILE = "/tmp/gql/automorphisms.txt";
+const std::string CENSUS_PATH       = "census/";
 
 int src_graph_num_edges = 0;
 
@@ -305,6 +308,105 @@ void make_input_patterns_canonical(int num_input_patterns) {
 }
 
 
+// Writes the automorphism group size of the target graph on the first line and,
+// on the second line, the (1-based) minimal orbit representative of every vertex.
+void find_pattern_automorphisms(bliss::Graph* target_graph) {
+  unsigned int num_vertices = target_graph->get_nof_vertices();
+  bliss::Orbit orbits;
+  orbits.init(num_vertices);
+  bliss::Stats stats;
+  target_graph->find_automorphisms(stats, [&orbits](unsigned int n, const unsigned int* aut) {
+    for (unsigned int i=0; i<n; i++)
+      orbits.merge_orbits(i, aut[i]);
+  });
+  std::ofstream outFile(AUTOMORPHISMS_FILE.c_str());
+  outFile << (unsigned long long)(stats.get_group_size_approx() + 0.5) << std::endl;
+  for (unsigned int i=0; i<num_vertices; i++) {
+    if (i > 0)
+      outFile << " ";
+    outFile << orbits.get_minimal_representative(i) + 1;
+  }
+  outFile << std::endl;
+  outFile.close();
+}
+
+
+bool is_connected(unsigned int num_vertices, const std::vector<std::tuple<int, int>>& edges) {
+  std::vector<unsigned int> component(num_vertices);
+  for (unsigned int i=0; i<num_vertices; i++)
+    component[i] = i;
+  bool changed = true;
+  while (changed) {
+    changed = false;
+    for (const auto& edge: edges) {
+      unsigned int u = std::get<0>(edge);
+      unsigned int v = std::get<1>(edge);
+      unsigned int smallest = std::min(component[u], component[v]);
+      if (component[u] != smallest || component[v] != smallest) {
+        component[u] = smallest;
+        component[v] = smallest;
+        changed = true;
+      }
+    }
+  }
+  for (unsigned int i=0; i<num_vertices; i++)
+    if (component[i] != 0)
+      return false;
+  return true;
+}
+
+
+// Same "folklore" enumeration as examples/enumerate.cc: every graph on k vertices is reached
+// by adding its last vertex to a graph on k-1 vertices, and isomorphic copies are cut off by
+// comparing canonical forms. Only the connected graphs on k vertices are written out.
+void traverse_census(unsigned int k, std::unordered_set<bliss::Graph*, GraphHash, GraphEqual>& seen, bliss::Graph* g, std::vector<std::tuple<int, int>>& edges, int& num_graphs) {
+  bliss::Stats stats;
+  bliss::Graph* g_canonical = g->permute(g->canonical_form(stats));
+  if (seen.find(g_canonical) != seen.end()) {
+    delete g_canonical;
+    return;
+  }
+  seen.insert(g_canonical);
+  const unsigned int num_vertices = g->get_nof_vertices();
+  if (num_vertices == k) {
+    if (is_connected(num_vertices, edges)) {
+      std::string file_path = DIRECTORY_PATH + CENSUS_PATH + std::to_string(num_graphs) + ".txt";
+      write_graph_to_file(file_path, g_canonical);
+      num_graphs++;
+    }
+    return;
+  }
+  for (unsigned long i = 0; i < (1UL << num_vertices); i++) {
+    bliss::Graph* child = g->copy();
+    int v = child->add_vertex();
+    size_t num_parent_edges = edges.size();
+    for (unsigned int j = 0; j < num_vertices; j++) {
+      if ((i >> j) & 0x01) {
+        child->add_edge(j, v);
+        edges.push_back(std::make_tuple(j, v));
+      }
+    }
+    traverse_census(k, seen, child, edges, num_graphs);
+    edges.resize(num_parent_edges);
+    delete child;
+  }
+}
+
+
+void enumerate_census(unsigned int k) {
+  std::string directoryPath = DIRECTORY_PATH + CENSUS_PATH;
+  mkdir(directoryPath.c_str(), 0777);
+  bliss::Graph* root = new bliss::Graph();
+  std::unordered_set<bliss::Graph*, GraphHash, GraphEqual> seen;
+  std::vector<std::tuple<int, int>> edges;
+  int num_graphs = 0;
+  traverse_census(k, seen, root, edges, num_graphs);
+  for (auto g: seen)
+    delete g;
+  delete root;
+}
+
+
 int main() {
   // bliss::Stats stats;
   // bliss::Graph* g = create_graph_from_file();
@@ -313,7 +415,14 @@ int main() {
   // canonical->write_dimacs(out);
   // fclose(out);
 
-  int num_input_patterns = stoi(read_from_pipe());
+  std::string first_message = read_from_pipe();
+  // "census k" asks for every connected k-vertex pattern before the input patterns arrive
+  if (first_message.rfind("census", 0) == 0) {
+    enumerate_census(stoi(first_message.substr(7)));
+    send_done_signal();
+    first_message = read_from_pipe();
+  }
+  int num_input_patterns = stoi(first_message);
   std::string ready_signal = read_from_pipe();
   make_input_patterns_canonical(num_input_patterns);
   send_done_signal();
@@ -321,6 +430,12 @@ int main() {
   std::string result_type = read_from_pipe();
   while (result_type != "done") {
     bliss::Graph* target_graph = create_graph_from_file();
+    if (result_type == "automorphisms") {
+      find_pattern_automorphisms(target_graph);
+      send_done_signal();
+      result_type = read_from_pipe();
+      continue;
+    }
     std::set<std::tuple<int, int>> disconnected_edges = get_disconnected_edges();
     std::vector<bliss::Graph*> graphs = generate_new_rules(target_graph, disconnected_edges);
     find_unique_graphs(graphs, target_graph);
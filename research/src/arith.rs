@@ -0,0 +1,112 @@
+use egg::*;
+use crate::*;
+
+
+// Products, differences and exact scalar division of counts, e.g. a disconnected pattern as
+//   (Sub (Mul (Morph pi P1) (Morph pi P2)) overlap)
+// or a subgraph count as (Div (Inj pi P) |Aut(P)|). Sub is rewritten into a Sum right away,
+// Mul distributes over Sum and lets Count coefficients out, and Div cancels against Count
+// and Sum coefficients that are multiples of the divisor. A Div that can't be cancelled is
+// still a valid plan, the division is done on the final count.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubToSum {
+    pub l: Var,
+    pub r: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for SubToSum {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let sum_id = match add_sum(egraph, vec![(1, subst[self.l]), (-1, subst[self.r])]) {
+            Some(sum_id) => sum_id,
+            None => return vec![],
+        };
+        if union_with_reason(egraph, matched_id, sum_id, rule_name) {
+            vec![sum_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MulDist {
+    pub l: Var,
+    pub terms: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for MulDist {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let l_id = subst[self.l];
+        let mut new_ids = vec![];
+        for terms in get_sum_nodes(egraph, subst[self.terms]) {
+            let mut new_terms = vec![];
+            for (coefficient, term) in terms {
+                new_terms.push((coefficient, egraph.add(SimpleLanguage::Mul([l_id, term]))));
+            }
+            let new_id = match add_sum(egraph, new_terms) {
+                Some(new_id) => new_id,
+                None => continue,
+            };
+            if union_with_reason(egraph, matched_id, new_id, rule_name) {
+                new_ids.push(new_id);
+            }
+        }
+        new_ids
+    }
+}
+
+// (Count n (Div a k)) and (Div (Count n a) k) are (Count n/k a) when k divides n.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DivCount {
+    pub num: Var,
+    pub rest: Var,
+    pub divisor: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for DivCount {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let num = get_num(egraph, subst[self.num]);
+        let divisor = match find_num(egraph, subst[self.divisor]) {
+            Some(divisor) if divisor != 0 && num % divisor == 0 => divisor,
+            _ => return vec![],
+        };
+        let new_num_id = egraph.add(SimpleLanguage::Num(num / divisor));
+        let count_id = egraph.add(SimpleLanguage::Count([new_num_id, subst[self.rest]]));
+        if union_with_reason(egraph, matched_id, count_id, rule_name) {
+            vec![count_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DivSum {
+    pub terms: Var,
+    pub divisor: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for DivSum {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let divisor = match find_num(egraph, subst[self.divisor]) {
+            Some(divisor) if divisor != 0 => divisor,
+            _ => return vec![],
+        };
+        let mut new_ids = vec![];
+        for terms in get_sum_nodes(egraph, subst[self.terms]) {
+            if terms.iter().any(|(coefficient, _)| coefficient % divisor != 0) {
+                continue;
+            }
+            let new_terms = terms.iter().map(|&(coefficient, term)| (coefficient / divisor, term)).collect();
+            let new_id = match add_sum(egraph, new_terms) {
+                Some(new_id) => new_id,
+                None => continue,
+            };
+            if union_with_reason(egraph, matched_id, new_id, rule_name) {
+                new_ids.push(new_id);
+            }
+        }
+        new_ids
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use egg::*;
+use crate::*;
+use crate::orbit::*;
+use crate::graph::*;
+use crate::utils::*;
+use crate::schedule::*;
+
+
+// Reference counts for the tests: every map of the pattern vertices into a small graph is
+// tried, so they only depend on what the labels mean. An edge needs adjacent images, an
+// anti-edge non-adjacent ones (which a homomorphism may merge) and a <> pair distinct ones.
+
+// A few fixed graphs with triangles, 4- and 5-cycles, cliques up to K5 and pendant
+// vertices, as adjacency matrices.
+pub fn test_graphs() -> Vec<Vec<Vec<bool>>> {
+    let petersen: Vec<(usize, usize)> = (0..5).flat_map(|i| vec![(i, (i + 1) % 5), (i, i + 5), (i + 5, (i + 2) % 5 + 5)]).collect();
+    let wheel: Vec<(usize, usize)> = (1..7).flat_map(|i| vec![(0, i), (i, i % 6 + 1)]).collect();
+    let mut clique_with_tail: Vec<(usize, usize)> = (0..5).flat_map(|i| (i + 1..5).map(move |j| (i, j))).collect();
+    clique_with_tail.extend(vec![(4, 5), (5, 6), (6, 7), (7, 5), (2, 7)]);
+    // a pseudo-random graph of density about one half, from a fixed linear congruential seed
+    let mut state: u64 = 12345;
+    let mut random = vec![];
+    for i in 0..9 {
+        for j in i + 1..9 {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            if (state >> 33).is_multiple_of(2) {
+                random.push((i, j));
+            }
+        }
+    }
+    vec![
+        adjacency_matrix(10, &petersen),
+        adjacency_matrix(7, &wheel),
+        adjacency_matrix(8, &clique_with_tail),
+        adjacency_matrix(9, &random),
+    ]
+}
+
+fn adjacency_matrix(num_nodes: usize, edges: &Vec<(usize, usize)>) -> Vec<Vec<bool>> {
+    let mut adjacency = vec![vec![false; num_nodes]; num_nodes];
+    for &(node1, node2) in edges {
+        adjacency[node1][node2] = true;
+        adjacency[node2][node1] = true;
+    }
+    adjacency
+}
+
+pub fn to_data_graph(adjacency: &Vec<Vec<bool>>) -> DataGraph {
+    let mut edges = HashSet::new();
+    for i in 0..adjacency.len() {
+        for j in i + 1..adjacency.len() {
+            if adjacency[i][j] {
+                edges.insert((i as i32, j as i32));
+            }
+        }
+    }
+    create_data_graph(&edges, adjacency.len())
+}
+
+// Maps of the labelled pattern into the graph, injective or homomorphisms.
+pub fn count_maps(matrix: &Vec<Vec<usize>>, adjacency: &Vec<Vec<bool>>, injective: bool) -> i64 {
+    extend_map(matrix, adjacency, injective, &mut vec![])
+}
+
+fn extend_map(matrix: &Vec<Vec<usize>>, adjacency: &Vec<Vec<bool>>, injective: bool, mapping: &mut Vec<usize>) -> i64 {
+    let vertex = mapping.len();
+    if vertex == matrix.len() {
+        return 1;
+    }
+    let mut maps = 0;
+    for node in 0..adjacency.len() {
+        let consistent = mapping.iter().enumerate().all(|(placed, &placed_node)| match matrix[vertex][placed] {
+            _ if injective && placed_node == node => false,
+            EDGE_LABEL => adjacency[node][placed_node],
+            ANTI_EDGE_LABEL => !adjacency[node][placed_node],
+            NOT_EQUAL_LABEL => placed_node != node,
+            _ => true,
+        });
+        if consistent {
+            mapping.push(node);
+            maps += extend_map(matrix, adjacency, injective, mapping);
+            mapping.pop();
+        }
+    }
+    maps
+}
+
+// Injective maps that send the anchor to node.
+pub fn count_anchored_embeddings(matrix: &Vec<Vec<usize>>, anchor: usize, node: usize, adjacency: &Vec<Vec<bool>>) -> i64 {
+    let mut order = vec![anchor];
+    order.extend((0..matrix.len()).filter(|&vertex| vertex != anchor));
+    let reordered: Vec<Vec<usize>> = order.iter().map(|&u| order.iter().map(|&v| matrix[u][v]).collect()).collect();
+    extend_map(&reordered, adjacency, true, &mut vec![node])
+}
+
+// Permutations of the pattern vertices that keep every label.
+pub fn count_automorphisms(matrix: &Vec<Vec<usize>>) -> i64 {
+    extend_automorphisms(matrix, vec![])
+}
+
+// Automorphisms that keep the anchor in place.
+pub fn count_anchored_automorphisms(matrix: &Vec<Vec<usize>>, anchor: usize) -> i64 {
+    let mut order = vec![anchor];
+    order.extend((0..matrix.len()).filter(|&vertex| vertex != anchor));
+    let reordered: Vec<Vec<usize>> = order.iter().map(|&u| order.iter().map(|&v| matrix[u][v]).collect()).collect();
+    extend_automorphisms(&reordered, vec![0])
+}
+
+pub fn extend_automorphisms(matrix: &Vec<Vec<usize>>, start: Vec<usize>) -> i64 {
+    let mut automorphisms = 0;
+    let mut stack: Vec<Vec<usize>> = vec![start];
+    while let Some(permutation) = stack.pop() {
+        if permutation.len() == matrix.len() {
+            automorphisms += 1;
+            continue;
+        }
+        let vertex = permutation.len();
+        for image in 0..matrix.len() {
+            if permutation.contains(&image) {
+                continue;
+            }
+            if (0..vertex).all(|placed| matrix[vertex][placed] == matrix[image][permutation[placed]]) {
+                let mut extended = permutation.clone();
+                extended.push(image);
+                stack.push(extended);
+            }
+        }
+    }
+    automorphisms
+}
+
+// Distinct subgraphs matching the labelled pattern.
+pub fn count_subgraphs(matrix: &Vec<Vec<usize>>, adjacency: &Vec<Vec<bool>>) -> i64 {
+    count_maps(matrix, adjacency, true) / count_automorphisms(matrix)
+}
+
+// Runs the named built-in rules, in one phase, on a single term.
+pub fn run_rules(term: &str, rule_names: &[&str]) -> (EGraph<SimpleLanguage, Optimizer>, Id, PhaseStatistics) {
+    let expr: RecExpr<SimpleLanguage> = term.parse().unwrap();
+    let phases = vec![Phase::new("test", rule_names, 10, 100_000, Duration::from_secs(60))];
+    let (egraph, root, mut statistics) = run_phases(&expr, Optimizer::new(1), &get_static_rewrite_rules(), &phases);
+    assert!(egraph.analysis.error.is_none(), "{:?}", egraph.analysis.error);
+    (egraph, root, statistics.pop().unwrap())
+}
+
+// The cost of the cheapest plan for term after the phases ran, with every pattern priced
+// |V(G)|^k for its k vertices in place of the cost estimator and the native terms priced the
+// way simplify prices them. morph asks peregrine for its expansions, so it never runs here.
+pub fn plan_cost(term: &str, phases: &Vec<Phase>, data_graph: &DataGraph) -> f64 {
+    let expr: RecExpr<SimpleLanguage> = term.parse().unwrap();
+    let rules: Vec<Rewrite<SimpleLanguage, Optimizer>> = get_static_rewrite_rules().into_iter()
+        .filter(|rule| rule.name.as_str() != "morph")
+        .collect();
+    let (mut egraph, root, _) = run_phases(&expr, Optimizer::new(1), &rules, phases);
+    assert!(egraph.analysis.error.is_none(), "{:?}", egraph.analysis.error);
+    let num_nodes = data_graph.num_nodes() as f64;
+    for pattern in egraph.analysis.patterns.clone() {
+        let cost = num_nodes.powi(pattern_to_label_matrix(&pattern).len() as i32);
+        egraph.analysis.cost_map.insert(pattern, cost);
+    }
+    find_formulas_costs(&mut egraph.analysis, data_graph);
+    find_complements_costs(&mut egraph.analysis, data_graph);
+    find_homs_costs(&mut egraph.analysis, data_graph);
+    let (cost, _) = Extractor::new(&egraph, NaiveCostFunction { egraph: &egraph }).find_best(root);
+    cost
+}
+
+// Checks every node of the e-class against the brute-force count, so the terms a rewrite
+// unioned into it are evaluated on the graph rather than trusted.
+pub fn check_class(egraph: &EGraph<SimpleLanguage, Optimizer>, id: Id, expected: i64, adjacency: &Vec<Vec<bool>>) {
+    for node in egraph[id].nodes.iter() {
+        let value = evaluate_node(egraph, node, adjacency, &mut vec![id]);
+        assert_eq!(value, Some(expected), "{}", node.build_recexpr(|child| egraph[child].nodes[0].clone()));
+    }
+}
+
+// Value of a count e-class on the graph, from the first of its nodes that can be evaluated
+// without going around a cycle. Morph, Inj and Hom nodes come first, they are counted
+// directly.
+pub fn evaluate_class(egraph: &EGraph<SimpleLanguage, Optimizer>, id: Id, adjacency: &Vec<Vec<bool>>, visiting: &mut Vec<Id>) -> Option<i64> {
+    let id = egraph.find(id);
+    if visiting.contains(&id) {
+        return None;
+    }
+    visiting.push(id);
+    let mut nodes: Vec<&SimpleLanguage> = egraph[id].nodes.iter().collect();
+    nodes.sort_by_key(|node| !matches!(node, SimpleLanguage::Morph(_) | SimpleLanguage::Inj(_) | SimpleLanguage::Hom(_)));
+    let value = nodes.into_iter().find_map(|node| evaluate_node(egraph, node, adjacency, visiting));
+    visiting.pop();
+    value
+}
+
+// Morph, Inj, Hom and Complement count by brute force, a Join from the brute-force local
+// counts of its sides and a Const through the formula evaluator. None for anything that is
+// not a count, and for a Div that does not divide.
+pub fn evaluate_node(egraph: &EGraph<SimpleLanguage, Optimizer>, node: &SimpleLanguage, adjacency: &Vec<Vec<bool>>, visiting: &mut Vec<Id>) -> Option<i64> {
+    let matrix = |id: Id| pattern_to_label_matrix(&format!("{}", egraph.id_to_expr(id)));
+    match node {
+        SimpleLanguage::Num(num) => Some(*num),
+        SimpleLanguage::Morph([_, pattern]) => Some(count_subgraphs(&matrix(*pattern), adjacency)),
+        SimpleLanguage::Inj([_, pattern]) => Some(count_maps(&matrix(*pattern), adjacency, true)),
+        SimpleLanguage::Hom([_, pattern]) => Some(count_maps(&matrix(*pattern), adjacency, false)),
+        SimpleLanguage::Complement([_, pattern]) => {
+            let complement: Vec<Vec<bool>> = (0..adjacency.len()).map(|i| (0..adjacency.len()).map(|j| i != j && !adjacency[i][j]).collect()).collect();
+            Some(count_subgraphs(&matrix(*pattern), &complement))
+        },
+        SimpleLanguage::Const([_, formula]) => {
+            let data_graph = to_data_graph(adjacency);
+            evaluate_const_formula(&format!("{}", egraph.id_to_expr(*formula)), &GraphStatistics::new(&data_graph)).ok()
+        },
+        SimpleLanguage::Join([first, second]) => {
+            let first = local_counts(egraph, *first, adjacency)?;
+            let second = local_counts(egraph, *second, adjacency)?;
+            Some(first.iter().zip(second.iter()).map(|(first_count, second_count)| first_count * second_count).sum())
+        },
+        SimpleLanguage::Sum(children) => {
+            let mut sum = 0;
+            for pair in children.chunks(2) {
+                sum += evaluate_class(egraph, pair[0], adjacency, visiting)? * evaluate_class(egraph, pair[1], adjacency, visiting)?;
+            }
+            Some(sum)
+        },
+        SimpleLanguage::Count([l, r]) | SimpleLanguage::Mul([l, r]) => {
+            Some(evaluate_class(egraph, *l, adjacency, visiting)? * evaluate_class(egraph, *r, adjacency, visiting)?)
+        },
+        SimpleLanguage::Sub([l, r]) => {
+            Some(evaluate_class(egraph, *l, adjacency, visiting)? - evaluate_class(egraph, *r, adjacency, visiting)?)
+        },
+        SimpleLanguage::Div([l, r]) => {
+            let (l, r) = (evaluate_class(egraph, *l, adjacency, visiting)?, evaluate_class(egraph, *r, adjacency, visiting)?);
+            if r != 0 && l % r == 0 { Some(l / r) } else { None }
+        },
+        _ => None,
+    }
+}
+
+// Per-vertex counts of the Local node of an e-class: anchored embeddings divided by the
+// automorphisms that fix the anchor.
+pub fn local_counts(egraph: &EGraph<SimpleLanguage, Optimizer>, id: Id, adjacency: &Vec<Vec<bool>>) -> Option<Vec<i64>> {
+    egraph[id].nodes.iter().find_map(|node| match node {
+        SimpleLanguage::Local([_, anchor, pattern]) => {
+            let matrix = pattern_to_label_matrix(&format!("{}", egraph.id_to_expr(*pattern)));
+            let anchor = alphabet_to_number(&format!("{}", egraph.id_to_expr(*anchor))) - 1;
+            let automorphisms = count_anchored_automorphisms(&matrix, anchor);
+            Some((0..adjacency.len()).map(|node| count_anchored_embeddings(&matrix, anchor, node, adjacency) / automorphisms).collect())
+        },
+        _ => None,
+    })
+}
@@ -0,0 +1,115 @@
+use egg::*;
+use crate::*;
+
+
+// A vertex-induced pattern is the same count as its complement on the complement of the data
+// graph: swapping edges and anti-edges on both sides keeps exactly the same vertex tuples.
+// Patterns with more anti-edges than edges get the alternative
+//   (Morph pi P) = (Complement pi P')
+// where P' is the complement of P. The complement of the data graph is never built, it is
+// the CSR read the other way round (a complement edge is a missing neighbour entry, the
+// complement degree is n - 1 - d), and count_complement_term matches P' on it directly.
+// Such patterns would otherwise be Morph-expanded into one super-pattern per subset of
+// their anti-edges.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComplementMorph {
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for ComplementMorph {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let pattern_string = format!("{}", egraph.id_to_expr(subst[self.pattern]));
+        let complement_pattern_string = "complement ".to_string() + &format!("{}", egraph.id_to_expr(provenance_id)) + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&complement_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(complement_pattern_string);
+        let matrix = pattern_to_label_matrix(&pattern_string);
+        if !is_anti_edge_heavy(&matrix) {
+            return vec![];
+        }
+        let (complement, _) = canonical_label_matrix(&complement_label_matrix(&matrix), None);
+        let complement_string = label_matrix_to_pattern(&complement);
+        egraph.analysis.register_complement(complement_string.clone());
+        let complement_recexpr: RecExpr<SimpleLanguage> = complement_string.parse().unwrap();
+        let complement_id = egraph.add_expr(&complement_recexpr);
+        let new_pattern = egraph.add(SimpleLanguage::Complement([provenance_id, complement_id]));
+        if union_with_reason(egraph, matched_id, new_pattern, rule_name) {
+            vec![new_pattern]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// Every pair labelled, so the pattern is vertex-induced, and more anti-edges than edges.
+fn is_anti_edge_heavy(matrix: &Vec<Vec<usize>>) -> bool {
+    let mut num_edges = 0;
+    let mut num_anti_edges = 0;
+    for i in 0..matrix.len() {
+        for j in i+1..matrix.len() {
+            match matrix[i][j] {
+                EDGE_LABEL => num_edges += 1,
+                ANTI_EDGE_LABEL => num_anti_edges += 1,
+                _ => return false,
+            }
+        }
+    }
+    num_anti_edges > num_edges
+}
+
+pub fn complement_label_matrix(matrix: &Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+    matrix.iter().map(|row| row.iter().map(|&label| match label {
+        EDGE_LABEL => ANTI_EDGE_LABEL,
+        ANTI_EDGE_LABEL => EDGE_LABEL,
+        label => label,
+    }).collect()).collect()
+}
+
+// Estimated steps of count_complement_term for the complement pattern, on a random graph as
+// dense as the data graph: a vertex takes the shorter of the average complement degree
+// n - 1 - d (across a pattern edge) and the average degree d (across an anti-edge) in
+// candidates, all n when nothing is placed next to it, and each further pair keeps a
+// candidate with the probability of a non-edge or of an edge.
+pub fn get_complement_cost(pattern: &str, data_graph: &DataGraph) -> f64 {
+    let matrix = pattern_to_label_matrix(pattern);
+    let num_nodes = data_graph.num_nodes() as f64;
+    let average_degree = 2.0 * data_graph.num_edges() as f64 / num_nodes.max(1.0);
+    let average_complement_degree = (num_nodes - 1.0 - average_degree).max(0.0);
+    let density = average_degree / (num_nodes - 1.0).max(1.0);
+    let order = matching_order(&matrix, 0);
+    let mut partial_embeddings = 1.0;
+    let mut cost = 0.0;
+    for (position, &vertex) in order.iter().enumerate() {
+        let mut candidates = num_nodes;
+        let mut source = None;
+        for &placed in order[..position].iter() {
+            let length = match matrix[vertex][placed] {
+                EDGE_LABEL => average_complement_degree,
+                ANTI_EDGE_LABEL => average_degree,
+                _ => continue,
+            };
+            if length < candidates {
+                candidates = length;
+                source = Some(placed);
+            }
+        }
+        cost += partial_embeddings * candidates;
+        partial_embeddings *= candidates;
+        for &placed in order[..position].iter() {
+            if Some(placed) == source {
+                continue;
+            }
+            match matrix[vertex][placed] {
+                EDGE_LABEL => partial_embeddings *= 1.0 - density,
+                ANTI_EDGE_LABEL => partial_embeddings *= density,
+                _ => {},
+            }
+        }
+    }
+    cost
+}
@@ -0,0 +1,218 @@
+use egg::*;
+use crate::*;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+
+// A pattern whose vertices fall apart into pieces with no label between them is counted
+// from its pieces instead of being Morph-expanded as a whole. With P = P1 + P2 (P1 the
+// component of a, P2 the rest), a pair of embeddings of P1 and P2 is an embedding of P
+// unless the two images share vertices, and every such pair is an embedding of the pattern
+// P/M obtained by merging the shared vertices M:
+//   inj(P) = inj(P1) inj(P2) - sum over non-empty matchings M between P1 and P2 of inj(P/M)
+// With inj(Q) = |Aut(Q)| sub(Q) and the quotients grouped by canonical form this becomes
+//   sub(P) = (|Aut(P1)| |Aut(P2)| sub(P1) sub(P2) - sum_Q m(Q) |Aut(Q)| sub(Q)) / |Aut(P)|
+// e.g. two disjoint edges = (4 m^2 - 8 wedges - 4 m) / 8. P2 and the quotients may still be
+// disconnected, the rule takes them apart in turn.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisconnectedMorph {
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for DisconnectedMorph {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let pattern_string = format!("{}", egraph.id_to_expr(subst[self.pattern]));
+        let disconnected_pattern_string = "disconnected ".to_string() + &format!("{}", egraph.id_to_expr(provenance_id)) + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&disconnected_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(disconnected_pattern_string);
+        let matrix = pattern_to_label_matrix(&pattern_string);
+        let components = find_label_components(&matrix);
+        if components.len() < 2 {
+            return vec![];
+        }
+        let first: Vec<usize> = components[0].clone();
+        let rest: Vec<usize> = components[1..].concat();
+        let (first_matrix, first_group_size) = canonical_label_matrix(&induced_label_matrix(&matrix, &first), None);
+        let (rest_matrix, rest_group_size) = canonical_label_matrix(&induced_label_matrix(&matrix, &rest), None);
+        let (_, group_size) = canonical_label_matrix(&matrix, None);
+
+        let first_id = add_morph_term(egraph, provenance_id, &first_matrix);
+        let rest_id = add_morph_term(egraph, provenance_id, &rest_matrix);
+        let product_id = egraph.add(SimpleLanguage::Mul([first_id, rest_id]));
+        let mut terms = vec![((first_group_size * rest_group_size) as i64, product_id)];
+        for (multiplicity, quotient, quotient_group_size) in find_merged_patterns(&matrix, &first, &rest) {
+            let quotient_id = add_morph_term(egraph, provenance_id, &quotient);
+            terms.push((-((multiplicity * quotient_group_size) as i64), quotient_id));
+        }
+        let sum_id = match add_sum(egraph, terms) {
+            Some(sum_id) => sum_id,
+            None => return vec![],
+        };
+        let group_size_id = egraph.add(SimpleLanguage::Num(group_size as i64));
+        let div_id = egraph.add(SimpleLanguage::Div([sum_id, group_size_id]));
+        if union_with_reason(egraph, matched_id, div_id, rule_name) {
+            vec![div_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+pub fn add_morph_term(egraph: &mut EGraph<SimpleLanguage, Optimizer>, provenance_id: Id, matrix: &Vec<Vec<usize>>) -> Id {
+    let pattern_string = label_matrix_to_pattern(matrix);
+    egraph.analysis.register_pattern(pattern_string.clone());
+    let pattern_recexpr: RecExpr<SimpleLanguage> = pattern_string.parse().unwrap();
+    let pattern_id = egraph.add_expr(&pattern_recexpr);
+    egraph.add(SimpleLanguage::Morph([provenance_id, pattern_id]))
+}
+
+// Connected components over every labelled pair, edges and anti-edges alike: an anti-edge
+// between two pieces constrains both, so they can't be counted apart.
+pub fn find_label_components(matrix: &Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+    let mut components = vec![];
+    let mut visited = vec![false; matrix.len()];
+    for start in 0..matrix.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut component = vec![start];
+        let mut queue = VecDeque::from([start]);
+        while let Some(vertex) = queue.pop_front() {
+            for other in 0..matrix.len() {
+                if !visited[other] && matrix[vertex][other] != NO_LABEL {
+                    visited[other] = true;
+                    component.push(other);
+                    queue.push_back(other);
+                }
+            }
+        }
+        component.sort();
+        components.push(component);
+    }
+    components
+}
+
+pub fn induced_label_matrix(matrix: &Vec<Vec<usize>>, vertices: &Vec<usize>) -> Vec<Vec<usize>> {
+    vertices.iter().map(|&u| vertices.iter().map(|&v| matrix[u][v]).collect()).collect()
+}
+
+// Every non-empty matching between first and rest, as (number of matchings, canonical
+// quotient, |Aut| of the quotient) per isomorphism class of the quotient. Vertices in
+// neither stay as they are. Matchings that put an edge and an anti-edge on the same pair
+// have no embeddings and are left out.
+pub fn find_merged_patterns(matrix: &Vec<Vec<usize>>, first: &Vec<usize>, rest: &Vec<usize>) -> Vec<(u64, Vec<Vec<usize>>, u64)> {
+    let mut classes: HashMap<Vec<Vec<usize>>, (u64, u64)> = HashMap::new();
+    let mut visit = |partners: &Vec<Option<usize>>| {
+        if partners.iter().all(|partner| partner.is_none()) {
+            return;
+        }
+        if let Some(quotient) = merge_label_matrix(matrix, first, rest, partners) {
+            let (canonical, group_size) = canonical_label_matrix(&quotient, None);
+            let entry = classes.entry(canonical).or_insert((0, group_size));
+            entry.0 += 1;
+        }
+    };
+    visit_matchings(rest.len(), 0, &mut vec![], &mut vec![false; rest.len()], first.len(), &mut visit);
+    let mut quotients = vec![];
+    for (canonical, (multiplicity, group_size)) in classes {
+        quotients.push((multiplicity, canonical, group_size));
+    }
+    quotients.sort_by_key(|(_, canonical, _)| label_matrix_to_pattern(canonical));
+    quotients
+}
+
+// partners[i] is the position in rest that the i-th vertex of first is merged with.
+fn visit_matchings(num_rest: usize, position: usize, partners: &mut Vec<Option<usize>>, used: &mut Vec<bool>, num_first: usize, visit: &mut dyn FnMut(&Vec<Option<usize>>)) {
+    if position == num_first {
+        visit(partners);
+        return;
+    }
+    partners.push(None);
+    visit_matchings(num_rest, position + 1, partners, used, num_first, visit);
+    partners.pop();
+    for partner in 0..num_rest {
+        if used[partner] {
+            continue;
+        }
+        used[partner] = true;
+        partners.push(Some(partner));
+        visit_matchings(num_rest, position + 1, partners, used, num_first, visit);
+        partners.pop();
+        used[partner] = false;
+    }
+}
+
+fn merge_label_matrix(matrix: &Vec<Vec<usize>>, first: &Vec<usize>, rest: &Vec<usize>, partners: &Vec<Option<usize>>) -> Option<Vec<Vec<usize>>> {
+    // vertices outside first keep their place, the ones of first either land on their
+    // partner or are appended
+    let mut quotient_vertex = vec![0; matrix.len()];
+    let mut num_nodes = 0;
+    for vertex in (0..matrix.len()).filter(|vertex| !first.contains(vertex)) {
+        quotient_vertex[vertex] = num_nodes;
+        num_nodes += 1;
+    }
+    for (position, &vertex) in first.iter().enumerate() {
+        quotient_vertex[vertex] = match partners[position] {
+            Some(partner) => quotient_vertex[rest[partner]],
+            None => {
+                num_nodes += 1;
+                num_nodes - 1
+            },
+        };
+    }
+    let mut quotient = vec![vec![NO_LABEL; num_nodes]; num_nodes];
+    for u in 0..matrix.len() {
+        for v in u+1..matrix.len() {
+            if matrix[u][v] == NO_LABEL {
+                continue;
+            }
+            let (qu, qv) = (quotient_vertex[u], quotient_vertex[v]);
+            if quotient[qu][qv] != NO_LABEL && quotient[qu][qv] != matrix[u][v] {
+                return None;
+            }
+            quotient[qu][qv] = matrix[u][v];
+            quotient[qv][qu] = matrix[u][v];
+        }
+    }
+    Some(quotient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brute_force::*;
+
+    #[test]
+    fn disconnected_patterns_match_brute_force_counts() {
+        let patterns = [
+            "(Match (-- a b) (-- c d))",
+            "(Match (-- a b) (-- c d) (-- c e) (-- d e))",
+            "(Match (-- a b) (!- a c) (-- d e))",
+            "(Match (-- a b) (-- c d) (-- e f))",
+        ];
+        for pattern in patterns {
+            let (egraph, root, _) = run_rules(&format!("(Morph (Pi 0) {})", pattern), &["disconnected_morph"]);
+            assert!(egraph[root].nodes.len() > 1, "{}", pattern);
+            for adjacency in test_graphs() {
+                check_class(&egraph, root, count_subgraphs(&pattern_to_label_matrix(pattern), &adjacency), &adjacency);
+            }
+        }
+    }
+
+    #[test]
+    fn disconnected_c4_component_reaches_escape_c4() {
+        let pattern = "(Match (-- a b) (-- b c) (-- c d) (-- a d) (-- e f))";
+        let (egraph, root, statistics) = run_rules(&format!("(Morph (Pi 0) {})", pattern), &["disconnected_morph", "escape-*"]);
+        assert!(statistics.applied.iter().any(|(rule, _)| rule == "escape-C4"), "{:?}", statistics.applied);
+        for adjacency in test_graphs() {
+            check_class(&egraph, root, count_subgraphs(&pattern_to_label_matrix(pattern), &adjacency), &adjacency);
+        }
+    }
+}
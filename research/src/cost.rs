@@ -1,112 +1,86 @@
 use egg::*;
-use libc::{rewind, CIBAUD, EPOLLRDHUP, GENL_UNS_ADMIN_PERM};
-use core::num;
-use std::collections::btree_map::Range;
-use std::collections::{HashMap, HashSet, *};
-use std::f64::consts;
-use std::fmt::format;
-use std::hash::Hash;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::ffi::CString;
-use std::fs::{File, DirEntry};
-use std::io::prelude::*;
-use std::fs::OpenOptions;
-use std::ops::Sub;
-use std::{env, fs, i64, process, result, usize};
-use std::cmp;
-use bimap::{BiMap, BiHashMap};
-use factorial::Factorial;
-use std::collections::VecDeque;
-use regex::{Match, Regex};
-use lazy_static::lazy_static;
-use std::sync::Mutex;
-use std::time::Instant;
-use std::cell::UnsafeCell;
-use std::process::Command;
-use std::str;
-use std::time::Duration;
+use crate::*;
 
-pub const DIRECTORY_PATH: &'static str       = "/tmp/gql/";
-pub const SRC_GRAPH_BLISS_FILE: &'static str = "src_graph.txt";
-pub const BLISS_PIPE_NAME: &'static str      = "my_pipe";
-pub const MORPH_PIPE_NAME: &'static str      = "morph_pipe";
-pub const RESULT_DIRECTORY: &'static str     = "result";
-pub const COST_DIRECTORY: &'static str       = "cost/";
-pub const SRC_DIRECTORY: &'static str        = "src/";
-pub const USER_RULES_PATH: &'static str      = "user_rules/";
-pub const DATA_GRAPH_PATH: &'static str      = "mico.lg";
-pub const PEREGRINE_DIRECTORY: &'static str  = "peregrine/";
 
-static NUM_PATTERNS: Mutex<usize> = Mutex::new(0);
+// Prices a plan with the Match costs estimated for this run, which live in the optimizer's
+// cost map. The e-graph is kept to rebuild the pattern under a Match or Const.
+pub struct NaiveCostFunction<'a> {
+    pub egraph: &'a EGraph<SimpleLanguage, Optimizer>,
+}
 
-pub struct NaiveCostFunction;
-impl CostFunction<SimpleLanguage> for NaiveCostFunction {
+impl<'a> CostFunction<SimpleLanguage> for NaiveCostFunction<'a> {
     type Cost = f64;
-    fn cost<C, D>(&mut self, enode: &SimpleLanguage, mut costs: C, get_node: D) -> Self::Cost
+    fn cost<C>(&mut self, enode: &SimpleLanguage, mut costs: C) -> Self::Cost
     where
         C: FnMut(Id) -> Self::Cost,
-        D: FnMut(Id) -> SimpleLanguage,
     {
+        let egraph = self.egraph;
+        // pattern and formula e-classes are never merged with anything else, so their only
+        // node is the one the plan extracts
+        let get_node = |id: Id| egraph[id].nodes[0].clone();
+        let cost_map = &egraph.analysis.cost_map;
         let node_string = format!("{:?}", enode);
         let index = node_string.find('(').unwrap();
         let node_type = &node_string[..index];
         if node_type == "Match" {
             let pattern = enode.build_recexpr(get_node); 
             let pattern_string = format!("{}", pattern);
-            let mut cost = 0.0;
-            unsafe {
-                let map = get_global_map();
-                cost = *map.get(&pattern_string).unwrap();
-                
-                // println!("{} {}", pattern_string, cost);
-            }
+            let cost = *cost_map.get(&pattern_string).unwrap_or(&f64::INFINITY);
+            // println!("{} {}", pattern_string, cost);
             // find_pattern_cost(&format!("{}", pattern));
             return enode.fold(cost, |sum, id| add_f64(sum, costs(id)));
         }
         else if node_type == "Const" {
             let pattern = enode.build_recexpr(get_node); 
             let pattern_string = format!("{}", pattern);
-            let (key_word, rest) = pattern_string.split_once(' ').unwrap();
-            let (provenance, formula) = parse_const_string(rest.to_string());
-            let mut cost = 0.0;
-            unsafe {
-                let map = get_global_map();
-                if map.contains_key(&formula) {
-                    cost = *map.get(&formula).unwrap();
-                }
+            let (_key_word, rest) = pattern_string.split_once(' ').unwrap();
+            let (_provenance, formula) = parse_const_string(rest.to_string());
+            // a tree can't share, so every Const pays for its statistics here; the plan's
+            // deduplicated cost counts them once
+            let mut cost = *cost_map.get(&formula).unwrap_or(&0.0);
+            for statistic in get_formula_statistics(&formula) {
+                cost = add_f64(cost, *cost_map.get(statistic).unwrap_or(&0.0));
             }
             // find_pattern_cost(&format!("{}", pattern));
             return enode.fold(cost, |sum, id| add_f64(sum, costs(id)));
         }
-        return enode.fold(0.0, |sum, id| add_f64(sum, costs(id)));
+        else if node_type == "Complement" {
+            // the pattern child is the complement, matched on the complement adjacency only, so
+            // it has no cost of its own and is never extracted as a Match
+            let pattern = enode.build_recexpr(get_node);
+            let pattern_string = format!("{}", pattern);
+            let (_key_word, rest) = pattern_string.split_once(' ').unwrap();
+            let (_provenance, complement) = parse_morph_string(rest.to_string());
+            let cost = *cost_map.get(&("(Complement ".to_string() + &complement + ")")).unwrap_or(&f64::INFINITY);
+            return add_f64(cost, costs(enode.children()[0]));
+        }
+        else if node_type == "Div" {
+            // Mul, Sub and Div only combine counts that are already there, but the division
+            // is only exact when it is by a constant
+            if let SimpleLanguage::Div([_, divisor]) = enode {
+                if !egraph[*divisor].nodes.iter().any(|node| matches!(node, SimpleLanguage::Num(_))) {
+                    return f64::INFINITY;
+                }
+            }
+            return enode.fold(0.0, |sum, id| add_f64(sum, costs(id)));
+        }
+        else if node_type == "Hom" {
+            // summed out by count_hom_term, the pattern child is never matched as a subgraph
+            let pattern = enode.build_recexpr(get_node);
+            let pattern_string = format!("{}", pattern);
+            let (_key_word, rest) = pattern_string.split_once(' ').unwrap();
+            let (_provenance, hom) = parse_morph_string(rest.to_string());
+            let cost = *cost_map.get(&("(Hom ".to_string() + &hom + ")")).unwrap_or(&f64::INFINITY);
+            return add_f64(cost, costs(enode.children()[0]));
+        }
+        else if node_type == "Inj" {
+            // embeddings are only a bridge between the sub and hom bases, no backend counts them
+            return f64::INFINITY;
+        }
+        enode.fold(0.0, |sum, id| add_f64(sum, costs(id)))
     }
 }
 
-fn test_function(rw: &Rewrite<SimpleLanguage, ()>) -> () {
-    // println!("hello");
-    // let node_string = format!("{:?}", rw);
-    // println!("{}", node_string);
-    // // let mut var = rewrite!("rule1"; "(Match (-- a b))" => "(Match (-- c c))");
-    // // rw = &mut var;
-    // println!("{:?}", rw);  
-}
-
-fn change_costs() {
-    unsafe {
-        let map = get_global_map();
-        map.insert("(Match p2)".to_string(), 0.0);
-        map.insert("(Match p5)".to_string(), 0.0);
-        map.insert("(Match p4)".to_string(), 0.0);
-    }
+pub fn add_f64(a: f64, b: f64) -> f64 {
+    a + b
 }
-
-fn custom_costs() {
-    unsafe {
-        let map = get_global_map();
-        map.insert("(Match p1)".to_string(), 100.0);
-        map.insert("(Match p2)".to_string(), 40.0);
-        map.insert("(Match p3)".to_string(), 50.0);
-        map.insert("(Match p4)".to_string(), 5.0);
-        map.insert("(Match p5)".to_string(), 8.0);
-    }
-}
\ No newline at end of file
@@ -0,0 +1,103 @@
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::fs;
+use std::error::Error;
+use std::io;
+use std::fmt;
+use std::str::FromStr;
+
+
+// Everything that can stop a run: malformed input or helper output (line and column are
+// 1-based), a file or pipe that can't be used, a helper binary that failed, a bad command
+// line argument, or a formula that has no integer value on the given counts.
+#[derive(Debug)]
+pub enum OptimizerError {
+    Parse {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    Backend {
+        command: String,
+        stderr: String,
+    },
+    Argument(String),
+    Evaluation(String),
+}
+
+impl OptimizerError {
+    pub fn parse(path: &str, line: usize, column: usize, message: &str) -> Self {
+        OptimizerError::Parse {
+            path: path.to_string(),
+            line: line,
+            column: column,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn io(path: &str, error: io::Error) -> Self {
+        OptimizerError::Io {
+            path: path.to_string(),
+            error: error,
+        }
+    }
+
+    pub fn backend(command: &str, stderr: &[u8]) -> Self {
+        OptimizerError::Backend {
+            command: command.to_string(),
+            stderr: String::from_utf8_lossy(stderr).trim().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for OptimizerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptimizerError::Parse { path, line, column, message } => write!(f, "{}:{}:{}: {}", path, line, column, message),
+            OptimizerError::Io { path, error } => write!(f, "{}: {}", path, error),
+            OptimizerError::Backend { command, stderr } if stderr.is_empty() => write!(f, "{} failed", command),
+            OptimizerError::Backend { command, stderr } => write!(f, "{} failed: {}", command, stderr),
+            OptimizerError::Argument(message) => write!(f, "{}", message),
+            OptimizerError::Evaluation(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for OptimizerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OptimizerError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+// Parses the index-th whitespace separated field of a line, pointing at the field (or at
+// the end of the line when it is missing) on failure.
+pub fn parse_field<T: FromStr>(line: &str, index: usize, path: &str, line_number: usize) -> Result<T, OptimizerError> {
+    let mut fields = line.split_whitespace();
+    let field = match fields.nth(index) {
+        Some(field) => field,
+        None => return Err(OptimizerError::parse(path, line_number, line.len() + 1, &format!("expected {} fields", index + 1))),
+    };
+    let column = field.as_ptr() as usize - line.as_ptr() as usize + 1;
+    field.parse::<T>().map_err(|_| OptimizerError::parse(path, line_number, column, &format!("unexpected '{}'", field)))
+}
+
+// An existing directory is fine, the pipeline reuses DIRECTORY_PATH between runs.
+pub fn create_directory(path: &str) -> Result<(), OptimizerError> {
+    match fs::create_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(error) => Err(OptimizerError::io(path, error)),
+    }
+}
+
+pub fn read_lines(path: &str) -> Result<Vec<String>, OptimizerError> {
+    let file = File::open(path).map_err(|error| OptimizerError::io(path, error))?;
+    BufReader::new(file).lines().collect::<Result<Vec<String>, io::Error>>().map_err(|error| OptimizerError::io(path, error))
+}
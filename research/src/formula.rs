@@ -0,0 +1,319 @@
+use egg::*;
+use crate::*;
+use std::collections::HashMap;
+use rayon::prelude::*;
+
+
+// Const formulas are closed expressions over the data graph, e.g. the number of 3-stars
+//   (SumV v (Choose (Deg v) 3))
+// SumV binds a vertex, SumE binds an edge in both orientations (u, v) and SumT binds a
+// triangle in all six orders (u, v, w). Inside them the statistics are
+//   (Deg x)       degree of x
+//   (Wedge x)     2-paths starting at x
+//   (TriV x)      triangles through x
+//   (TriE x y)    triangles through the edge (x, y)
+//   (C4V x)       4-cycles through x
+//   (Walk x k)    closed walks of length k at x, the diagonal of A^k, k up to MAX_WALK_LENGTH
+// combined with (Choose n k), (Times a b), (Minus a b), exact (Quot a k) and integers.
+// The formula is the Const's second child, so it is printed with the plan and two escape
+// rules that end up with the same formula share one Const.
+
+// Normal form of a formula: constants folded, multiplication and subtraction by 0 or 1
+// dropped and the operands of Times in order, so formulas that only differ in how they were
+// written meet in the same e-class.
+pub fn simplify_const_formula(formula: &str) -> String {
+    let expr: RecExpr<SimpleLanguage> = formula.parse().unwrap();
+    simplify_formula_node(&expr, Id::from(expr.as_ref().len() - 1))
+}
+
+fn simplify_formula_node(expr: &RecExpr<SimpleLanguage>, id: Id) -> String {
+    let node = &expr[id];
+    let children: Vec<String> = node.children().iter().map(|&child| simplify_formula_node(expr, child)).collect();
+    let numbers: Vec<Option<i64>> = children.iter().map(|child| child.parse().ok()).collect();
+    let rebuilt = || {
+        let operator = format!("{}", node);
+        if children.is_empty() {
+            operator
+        }
+        else {
+            "(".to_string() + &operator + " " + &children.join(" ") + ")"
+        }
+    };
+    match node {
+        SimpleLanguage::Times(_) => match (numbers[0], numbers[1]) {
+            (Some(0), _) | (_, Some(0)) => "0".to_string(),
+            (Some(1), _) => children[1].clone(),
+            (_, Some(1)) => children[0].clone(),
+            (Some(left), Some(right)) if left.checked_mul(right).is_some() => (left * right).to_string(),
+            _ if children[1] < children[0] => "(Times ".to_string() + &children[1] + " " + &children[0] + ")",
+            _ => rebuilt(),
+        },
+        SimpleLanguage::Minus(_) => match (numbers[0], numbers[1]) {
+            (_, Some(0)) => children[0].clone(),
+            (Some(left), Some(right)) if left.checked_sub(right).is_some() => (left - right).to_string(),
+            _ if children[0] == children[1] => "0".to_string(),
+            _ => rebuilt(),
+        },
+        SimpleLanguage::Quot(_) => match (numbers[0], numbers[1]) {
+            (_, Some(1)) => children[0].clone(),
+            (Some(left), Some(right)) if right != 0 && left % right == 0 => (left / right).to_string(),
+            _ => rebuilt(),
+        },
+        SimpleLanguage::Choose(_) => match (numbers[0], numbers[1]) {
+            (_, Some(0)) => "1".to_string(),
+            (_, Some(1)) => children[0].clone(),
+            (Some(n), Some(k)) => checked_binomial(n, k).map(|value| value.to_string()).unwrap_or_else(rebuilt),
+            _ => rebuilt(),
+        },
+        _ => rebuilt(),
+    }
+}
+
+// C(n, k), 0 when n < k, None on overflow.
+fn checked_binomial(n: i64, k: i64) -> Option<i64> {
+    if k < 0 || n < k {
+        return Some(0);
+    }
+    let mut value: i64 = 1;
+    for i in 0..k {
+        value = value.checked_mul(n - i)? / (i + 1);
+    }
+    Some(value)
+}
+
+// The precomputed statistics a formula reads. A plan pays for each of them once, however
+// many of its Const formulas read it, so they are priced apart from the formulas, under
+// their node names in the cost map. Degrees come with the CSR and are free.
+pub fn get_formula_statistics(formula: &str) -> Vec<&'static str> {
+    let expr: RecExpr<SimpleLanguage> = formula.parse().unwrap();
+    let mut statistics = vec![];
+    for node in expr.as_ref() {
+        let statistic = match node {
+            SimpleLanguage::Wedge(_) => "Wedge",
+            SimpleLanguage::TriV(_) => "TriV",
+            SimpleLanguage::TriE(_) => "TriE",
+            SimpleLanguage::C4V(_) => "C4V",
+            SimpleLanguage::Walk([_, length]) => match get_walk_length(&expr, *length) {
+                Some(length) => WALK_STATISTICS[length],
+                None => continue,
+            },
+            _ => continue,
+        };
+        if !statistics.contains(&statistic) {
+            statistics.push(statistic);
+        }
+    }
+    statistics
+}
+
+// The patterns whose estimated match cost prices a formula: triangle enumeration for the
+// triangle statistics and for a loop over triangles, 4-cycle enumeration for C4V and for
+// closed walks the paths out of every vertex over half the walk.
+pub fn get_formula_cost_patterns(formula: &str) -> Vec<&'static str> {
+    let expr: RecExpr<SimpleLanguage> = formula.parse().unwrap();
+    let mut patterns = vec![];
+    for node in expr.as_ref() {
+        let pattern = match node {
+            SimpleLanguage::TriV(_) | SimpleLanguage::TriE(_) | SimpleLanguage::SumT(_) => TRIANGLE_PATTERN,
+            SimpleLanguage::C4V(_) => FOUR_CYCLE_PATTERN,
+            SimpleLanguage::Walk([_, length]) => match get_walk_length(&expr, *length).and_then(get_walk_cost_pattern) {
+                Some(pattern) => pattern,
+                None => continue,
+            },
+            _ => continue,
+        };
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    patterns
+}
+
+// What a formula costs on top of its statistics: one step per vertex of a SumV, per
+// oriented edge of a SumE and per triangle of a SumT, which is as dear as finding them.
+pub fn get_formula_loop_cost(formula: &str, data_graph: &DataGraph, cost_map: &HashMap<String, f64>) -> f64 {
+    let expr: RecExpr<SimpleLanguage> = formula.parse().unwrap();
+    expr.as_ref().iter().map(|node| match node {
+        SimpleLanguage::SumV(_) => data_graph.num_nodes() as f64,
+        SimpleLanguage::SumE(_) => 2.0 * data_graph.num_edges() as f64,
+        SimpleLanguage::SumT(_) => get_pattern_cost(cost_map, TRIANGLE_PATTERN),
+        _ => 0.0,
+    }).sum()
+}
+
+pub fn get_statistic_cost(statistic: &str, data_graph: &DataGraph, cost_map: &HashMap<String, f64>) -> f64 {
+    match statistic {
+        "Wedge" => 2.0 * data_graph.num_edges() as f64,
+        "TriV" | "TriE" => get_pattern_cost(cost_map, TRIANGLE_PATTERN),
+        "C4V" => get_pattern_cost(cost_map, FOUR_CYCLE_PATTERN),
+        _ => match WALK_STATISTICS.iter().position(|&name| name == statistic) {
+            Some(length) => match get_walk_cost_pattern(length) {
+                Some(pattern) => get_pattern_cost(cost_map, pattern),
+                None => 2.0 * data_graph.num_edges() as f64,
+            },
+            None => 0.0,
+        },
+    }
+}
+
+// A pattern that was never priced can't be matched, so whatever needs it is out of reach.
+pub fn get_pattern_cost(cost_map: &HashMap<String, f64>, pattern: &str) -> f64 {
+    *cost_map.get(pattern).unwrap_or(&f64::INFINITY)
+}
+
+// Closed walks are priced per length, a formula over 4-walks doesn't pay for 5-walks.
+const WALK_STATISTICS: [&str; MAX_WALK_LENGTH + 1] = ["Walk0", "Walk1", "Walk2", "Walk3", "Walk4", "Walk5"];
+
+fn get_walk_length(expr: &RecExpr<SimpleLanguage>, length: Id) -> Option<usize> {
+    match &expr[length] {
+        SimpleLanguage::Num(length) if (0..=MAX_WALK_LENGTH as i64).contains(length) => Some(*length as usize),
+        _ => None,
+    }
+}
+
+// Counting walks of length k spreads walk counts over ceil(k / 2) hops, as many as there are
+// paths of that length out of every vertex. One hop is a pass over the edges.
+fn get_walk_cost_pattern(length: usize) -> Option<&'static str> {
+    match length.div_ceil(2) {
+        2 => Some(WEDGE_PATTERN),
+        3 => Some(THREE_PATH_PATTERN),
+        _ => None,
+    }
+}
+
+// The value of a Const formula on the data graph. The statistics come from the store, so
+// evaluating all formulas of a plan against one store computes each statistic once.
+pub fn evaluate_const_formula(formula: &str, statistics: &GraphStatistics) -> Result<i64, OptimizerError> {
+    let expr: RecExpr<SimpleLanguage> = formula.parse()
+        .map_err(|_| OptimizerError::Evaluation(format!("{} is not a Const formula", formula)))?;
+    let evaluator = FormulaEvaluator {
+        formula: formula,
+        statistics: statistics,
+    };
+    evaluator.evaluate(&expr, Id::from(expr.as_ref().len() - 1), &mut HashMap::new())
+}
+
+struct FormulaEvaluator<'a> {
+    formula: &'a str,
+    statistics: &'a GraphStatistics<'a>,
+}
+
+impl<'a> FormulaEvaluator<'a> {
+    fn error(&self, message: &str) -> OptimizerError {
+        OptimizerError::Evaluation(format!("{}: {}", self.formula, message))
+    }
+
+    fn vertex(&self, expr: &RecExpr<SimpleLanguage>, id: Id, bindings: &HashMap<Symbol, u32>) -> Result<u32, OptimizerError> {
+        match &expr[id] {
+            SimpleLanguage::Symbol(name) => bindings.get(name).copied().ok_or_else(|| self.error(&format!("'{}' is not bound", name))),
+            node => Err(self.error(&format!("expected a vertex, found {}", node))),
+        }
+    }
+
+    fn bind(&self, expr: &RecExpr<SimpleLanguage>, id: Id) -> Result<Symbol, OptimizerError> {
+        match &expr[id] {
+            SimpleLanguage::Symbol(name) => Ok(*name),
+            node => Err(self.error(&format!("expected a vertex name, found {}", node))),
+        }
+    }
+
+    fn sum_over(&self, total: i64, value: i64) -> Result<i64, OptimizerError> {
+        total.checked_add(value).ok_or_else(|| self.error("overflows"))
+    }
+
+    // Adds up value over the data vertices on the rayon pool. Every job binds its vertices in
+    // its own copy of the bindings and keeps its own total, the totals are added at the end.
+    fn sum_over_vertices<F>(&self, bindings: &HashMap<Symbol, u32>, value: F) -> Result<i64, OptimizerError>
+    where
+        F: Fn(&mut HashMap<Symbol, u32>, u32) -> Result<i64, OptimizerError> + Sync + Send,
+    {
+        (0..self.statistics.data_graph().num_nodes() as u32).into_par_iter()
+            .try_fold(|| (bindings.clone(), 0), |(mut bindings, total), node| {
+                let total = self.sum_over(total, value(&mut bindings, node)?)?;
+                Ok((bindings, total))
+            })
+            .map(|accumulator| accumulator.map(|(_, total)| total))
+            .try_reduce(|| 0, |left, right| self.sum_over(left, right))
+    }
+
+    fn evaluate(&self, expr: &RecExpr<SimpleLanguage>, id: Id, bindings: &mut HashMap<Symbol, u32>) -> Result<i64, OptimizerError> {
+        let statistics = self.statistics;
+        let data_graph = statistics.data_graph();
+        match &expr[id] {
+            SimpleLanguage::Num(value) => Ok(*value),
+            SimpleLanguage::SumV([vertex, body]) => {
+                let name = self.bind(expr, *vertex)?;
+                self.sum_over_vertices(bindings, |bindings, node| {
+                    bindings.insert(name, node);
+                    self.evaluate(expr, *body, bindings)
+                })
+            },
+            SimpleLanguage::SumE([vertex1, vertex2, body]) => {
+                let (name1, name2) = (self.bind(expr, *vertex1)?, self.bind(expr, *vertex2)?);
+                self.sum_over_vertices(bindings, |bindings, node1| {
+                    let mut total = 0;
+                    for &node2 in data_graph.neighbours(node1) {
+                        bindings.insert(name1, node1);
+                        bindings.insert(name2, node2);
+                        total = self.sum_over(total, self.evaluate(expr, *body, bindings)?)?;
+                    }
+                    Ok(total)
+                })
+            },
+            SimpleLanguage::SumT([vertex1, vertex2, vertex3, body]) => {
+                let names = [self.bind(expr, *vertex1)?, self.bind(expr, *vertex2)?, self.bind(expr, *vertex3)?];
+                self.sum_over_vertices(bindings, |bindings, node1| {
+                    let mut total = 0;
+                    for &node2 in data_graph.neighbours(node1) {
+                        for &node3 in data_graph.neighbours(node1) {
+                            if !data_graph.has_edge(node2, node3) {
+                                continue;
+                            }
+                            for (name, node) in names.iter().zip([node1, node2, node3]) {
+                                bindings.insert(*name, node);
+                            }
+                            total = self.sum_over(total, self.evaluate(expr, *body, bindings)?)?;
+                        }
+                    }
+                    Ok(total)
+                })
+            },
+            SimpleLanguage::Deg(vertex) => Ok(statistics.degrees()[self.vertex(expr, *vertex, bindings)? as usize] as i64),
+            SimpleLanguage::Wedge(vertex) => Ok(statistics.wedges()[self.vertex(expr, *vertex, bindings)? as usize] as i64),
+            SimpleLanguage::TriV(vertex) => Ok(statistics.vertex_triangles()[self.vertex(expr, *vertex, bindings)? as usize] as i64),
+            SimpleLanguage::TriE([vertex1, vertex2]) => {
+                let (node1, node2) = (self.vertex(expr, *vertex1, bindings)?, self.vertex(expr, *vertex2, bindings)?);
+                // a pair that isn't an edge still has its common neighbours
+                let triangles = statistics.edge_value(statistics.edge_triangles(), node1, node2)
+                    .unwrap_or_else(|| data_graph.common_neighbour_count(node1, node2));
+                Ok(triangles as i64)
+            },
+            SimpleLanguage::C4V(vertex) => Ok(statistics.vertex_four_cycles()[self.vertex(expr, *vertex, bindings)? as usize] as i64),
+            SimpleLanguage::Walk([vertex, length]) => {
+                let length = get_walk_length(expr, *length)
+                    .ok_or_else(|| self.error(&format!("walk lengths go up to {}", MAX_WALK_LENGTH)))?;
+                Ok(statistics.closed_walks(length)[self.vertex(expr, *vertex, bindings)? as usize] as i64)
+            },
+            SimpleLanguage::Choose([n, k]) => {
+                let (n, k) = (self.evaluate(expr, *n, bindings)?, self.evaluate(expr, *k, bindings)?);
+                checked_binomial(n, k).ok_or_else(|| self.error("overflows"))
+            },
+            SimpleLanguage::Times([left, right]) => {
+                let (left, right) = (self.evaluate(expr, *left, bindings)?, self.evaluate(expr, *right, bindings)?);
+                left.checked_mul(right).ok_or_else(|| self.error("overflows"))
+            },
+            SimpleLanguage::Minus([left, right]) => {
+                let (left, right) = (self.evaluate(expr, *left, bindings)?, self.evaluate(expr, *right, bindings)?);
+                left.checked_sub(right).ok_or_else(|| self.error("overflows"))
+            },
+            SimpleLanguage::Quot([left, right]) => {
+                let (left, right) = (self.evaluate(expr, *left, bindings)?, self.evaluate(expr, *right, bindings)?);
+                if right == 0 || left % right != 0 {
+                    return Err(self.error(&format!("{} / {} is not an integer", left, right)));
+                }
+                Ok(left / right)
+            },
+            node => Err(self.error(&format!("unexpected {}", node))),
+        }
+    }
+}
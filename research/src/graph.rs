@@ -0,0 +1,507 @@
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+use std::fs::File;
+use std::fs;
+use std::cmp;
+use std::time::UNIX_EPOCH;
+use rayon::prelude::*;
+
+pub const DIRECTORY_PATH: &str       = "/tmp/gql/";
+pub const SRC_GRAPH_BLISS_FILE: &str = "src_graph.txt";
+pub const BLISS_PIPE_NAME: &str      = "my_pipe";
+pub const MORPH_PIPE_NAME: &str      = "morph_pipe";
+pub const RESULT_DIRECTORY: &str     = "result";
+pub const COST_DIRECTORY: &str       = "cost/";
+pub const SRC_DIRECTORY: &str        = "src/";
+pub const DATA_GRAPH_PATH: &str      = "mico.lg";
+pub const PEREGRINE_DIRECTORY: &str  = "peregrine/";
+pub const AUTOMORPHISMS_FILE: &str   = "automorphisms.txt";
+pub const CENSUS_DIRECTORY: &str     = "census/";
+pub const DATA_GRAPH_EDGE_LIST: &str = "data_graph.txt";
+pub const MORPH_MEMO_PATH: &str      = "morph_memo.txt";
+pub const DATA_GRAPH_CACHE_EXTENSION: &str = ".csr";
+pub const DATA_GRAPH_CACHE_MAGIC: &[u8; 8] = b"GQLCSR03";
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataGraphFormat {
+    Snap,
+    Lg,
+    MatrixMarket,
+    Dimacs,
+}
+
+// Undirected simple graph shared by the escape constants, the local counting engine and
+// the cost estimator, stored as CSR: the neighbours of dense vertex v are
+// neighbours[offsets[v]..offsets[v+1]], sorted and without self loops or repeated edges.
+// node_ids maps dense vertices back to the ids used in the input file.
+pub struct DataGraph {
+    offsets: Vec<u32>,
+    neighbours: Vec<u32>,
+    pub node_ids: Vec<i32>,
+}
+
+impl DataGraph {
+    pub fn num_nodes(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    pub fn num_edges(&self) -> usize {
+        self.neighbours.len() / 2
+    }
+
+    pub fn neighbours(&self, node: u32) -> &[u32] {
+        &self.neighbours[self.offsets[node as usize] as usize..self.offsets[node as usize + 1] as usize]
+    }
+
+    // positions of the edges of node in the neighbour array, which index per-edge statistics
+    pub fn edge_range(&self, node: u32) -> std::ops::Range<usize> {
+        self.offsets[node as usize] as usize..self.offsets[node as usize + 1] as usize
+    }
+
+    pub fn degree(&self, node: u32) -> usize {
+        (self.offsets[node as usize + 1] - self.offsets[node as usize]) as usize
+    }
+
+    pub fn has_edge(&self, node1: u32, node2: u32) -> bool {
+        let (small, large) = if self.degree(node1) <= self.degree(node2) { (node1, node2) } else { (node2, node1) };
+        self.neighbours(small).binary_search(&large).is_ok()
+    }
+
+    // number of triangles through the edge (node1, node2), by merging the sorted lists
+    pub fn common_neighbour_count(&self, node1: u32, node2: u32) -> usize {
+        let first = self.neighbours(node1);
+        let second = self.neighbours(node2);
+        let (mut i, mut j, mut count) = (0, 0, 0);
+        while i < first.len() && j < second.len() {
+            if first[i] < second[j] {
+                i += 1;
+            }
+            else if first[i] > second[j] {
+                j += 1;
+            }
+            else {
+                count += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+        count
+    }
+
+    // number of triangles through every vertex, each triangle found once from its
+    // lowest edge (u < v < w). Every rayon job keeps its own counters, which are added up
+    // at the end, so the result does not depend on the scheduling.
+    pub fn vertex_triangle_counts(&self) -> Vec<usize> {
+        let num_nodes = self.num_nodes();
+        (0..num_nodes as u32).into_par_iter().fold(|| vec![0; num_nodes], |mut triangles, node1| {
+            let node1_neighbours = self.neighbours(node1);
+            for &node2 in node1_neighbours.iter().filter(|&&node2| node2 > node1) {
+                for &node3 in self.neighbours(node2).iter().filter(|&&node3| node3 > node2) {
+                    if node1_neighbours.binary_search(&node3).is_ok() {
+                        triangles[node1 as usize] += 1;
+                        triangles[node2 as usize] += 1;
+                        triangles[node3 as usize] += 1;
+                    }
+                }
+            }
+            triangles
+        }).reduce(|| vec![0; num_nodes], |mut total, triangles| {
+            for i in 0..num_nodes {
+                total[i] += triangles[i];
+            }
+            total
+        })
+    }
+}
+
+fn parse_data_graph_format(name: &str) -> Result<DataGraphFormat, OptimizerError> {
+    match name {
+        "snap" | "txt" | "edges" | "el" => Ok(DataGraphFormat::Snap),
+        "lg" => Ok(DataGraphFormat::Lg),
+        "mtx" | "mm" => Ok(DataGraphFormat::MatrixMarket),
+        "dimacs" | "gr" | "col" => Ok(DataGraphFormat::Dimacs),
+        _ => Err(OptimizerError::Argument(format!("unknown data graph format '{}', expected snap, lg, mtx or dimacs", name))),
+    }
+}
+
+// The format guessed from the extension of path, SNAP when the extension is unknown.
+pub fn data_graph_format_from_path(path: &str) -> DataGraphFormat {
+    match path.rsplit_once('.') {
+        Some((_, extension)) => parse_data_graph_format(extension).unwrap_or(DataGraphFormat::Snap),
+        None => DataGraphFormat::Snap,
+    }
+}
+
+// `--graph <path>` picks the data graph and `--format <snap|lg|mtx|dimacs>` overrides the
+// format guessed from the extension. Without flags the default DATA_GRAPH_PATH is read.
+pub fn get_data_graph_source(args: &Vec<String>) -> Result<(String, DataGraphFormat), OptimizerError> {
+    let path = get_flag_value(args, "--graph").unwrap_or(DATA_GRAPH_PATH.to_string());
+    let format = match get_flag_value(args, "--format") {
+        Some(name) => parse_data_graph_format(&name)?,
+        None => data_graph_format_from_path(&path),
+    };
+    Ok((path, format))
+}
+
+pub fn get_flag_value(args: &Vec<String>, flag: &str) -> Option<String> {
+    let position = args.iter().position(|arg| arg == flag)?;
+    args.get(position + 1).cloned()
+}
+
+// Parsing text graphs dominates on large inputs, so the CSR arrays are cached next to the
+// source file and reused until the source changes or it is read as another format.
+pub fn load_data_graph(path: &str, format: DataGraphFormat) -> Result<DataGraph, OptimizerError> {
+    let cache_path = path.to_string() + DATA_GRAPH_CACHE_EXTENSION;
+    let source_stamp = get_data_graph_source_stamp(path);
+    if let Some(source_stamp) = source_stamp {
+        if let Some(data_graph) = read_data_graph_cache(&cache_path, format, source_stamp) {
+            return Ok(data_graph);
+        }
+    }
+    let file = File::open(path).map_err(|error| OptimizerError::io(path, error))?;
+    let reader = BufReader::new(file);
+    let mut edges = HashSet::new();
+    let mut num_nodes = 0;
+    match format {
+        DataGraphFormat::Snap => read_snap_edges(reader, path, &mut edges)?,
+        DataGraphFormat::Lg => read_lg_edges(reader, path, &mut edges, &mut num_nodes)?,
+        DataGraphFormat::MatrixMarket => read_matrix_market_edges(reader, path, &mut edges, &mut num_nodes)?,
+        DataGraphFormat::Dimacs => read_dimacs_edges(reader, path, &mut edges, &mut num_nodes)?,
+    }
+    let data_graph = create_data_graph(&edges, num_nodes);
+    if let Some(source_stamp) = source_stamp {
+        write_data_graph_cache(&data_graph, format, source_stamp, &cache_path);
+    }
+    Ok(data_graph)
+}
+
+fn insert_data_graph_edge(edges: &mut HashSet<(i32, i32)>, node1: i32, node2: i32) {
+    if node1 != node2 {
+        edges.insert((cmp::min(node1, node2), cmp::max(node1, node2)));
+    }
+}
+
+// The two node ids starting at the first_field-th field of a record.
+fn parse_two_nodes(line: &str, first_field: usize, path: &str, line_number: usize) -> Result<(i32, i32), OptimizerError> {
+    let node1 = parse_field(line, first_field, path, line_number)?;
+    let node2 = parse_field(line, first_field + 1, path, line_number)?;
+    Ok((node1, node2))
+}
+
+// The two 1-based node ids starting at the first_field-th field of a record, as 0-based ids.
+fn parse_two_one_based_nodes(line: &str, first_field: usize, path: &str, line_number: usize) -> Result<(i32, i32), OptimizerError> {
+    let (node1, node2) = parse_two_nodes(line, first_field, path, line_number)?;
+    for (offset, node) in [node1, node2].into_iter().enumerate() {
+        if node < 1 {
+            let field = line.split_whitespace().nth(first_field + offset).unwrap();
+            let column = field.as_ptr() as usize - line.as_ptr() as usize + 1;
+            return Err(OptimizerError::parse(path, line_number, column, &format!("node ids start at 1, found {}", node)));
+        }
+    }
+    Ok((node1 - 1, node2 - 1))
+}
+
+// SNAP edge lists: one "u v" pair per line, '#' and '%' start comments.
+fn read_snap_edges(reader: BufReader<File>, path: &str, edges: &mut HashSet<(i32, i32)>) -> Result<(), OptimizerError> {
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| OptimizerError::io(path, error))?;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('%') {
+            continue;
+        }
+        let (node1, node2) = parse_two_nodes(&line, 0, path, index + 1)?;
+        insert_data_graph_edge(edges, node1, node2);
+    }
+    Ok(())
+}
+
+// Peregrine/GraphMini .lg files: "v id label" and "e u v [label]" records, "t" headers.
+// Plain "u v" pairs are accepted as well, which is how mico.lg is stored.
+fn read_lg_edges(reader: BufReader<File>, path: &str, edges: &mut HashSet<(i32, i32)>, num_nodes: &mut usize) -> Result<(), OptimizerError> {
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| OptimizerError::io(path, error))?;
+        let line_number = index + 1;
+        match line.split_whitespace().next() {
+            None | Some("t") | Some("#") => {},
+            Some("v") => {
+                let node = parse_field::<usize>(&line, 1, path, line_number)?;
+                *num_nodes = cmp::max(*num_nodes, node + 1);
+            },
+            Some("e") => {
+                let (node1, node2) = parse_two_nodes(&line, 1, path, line_number)?;
+                insert_data_graph_edge(edges, node1, node2);
+            },
+            Some(_) => {
+                let (node1, node2) = parse_two_nodes(&line, 0, path, line_number)?;
+                insert_data_graph_edge(edges, node1, node2);
+            },
+        }
+    }
+    Ok(())
+}
+
+// Matrix Market coordinate files: '%' comments, a "rows cols entries" size line and then
+// 1-based "i j [value]" entries. Both symmetric and general matrices are read as undirected.
+fn read_matrix_market_edges(reader: BufReader<File>, path: &str, edges: &mut HashSet<(i32, i32)>, num_nodes: &mut usize) -> Result<(), OptimizerError> {
+    let mut read_size_line = false;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| OptimizerError::io(path, error))?;
+        let line_number = index + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('%') {
+            continue;
+        }
+        if !read_size_line {
+            let rows = parse_field::<usize>(&line, 0, path, line_number)?;
+            let columns = parse_field::<usize>(&line, 1, path, line_number)?;
+            *num_nodes = cmp::max(rows, columns);
+            read_size_line = true;
+            continue;
+        }
+        let (node1, node2) = parse_two_one_based_nodes(&line, 0, path, line_number)?;
+        insert_data_graph_edge(edges, node1, node2);
+    }
+    Ok(())
+}
+
+// DIMACS graphs: 'c' comments, a "p edge n m" (or "p sp n m") header and 1-based
+// "e u v" / "a u v w" records.
+fn read_dimacs_edges(reader: BufReader<File>, path: &str, edges: &mut HashSet<(i32, i32)>, num_nodes: &mut usize) -> Result<(), OptimizerError> {
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|error| OptimizerError::io(path, error))?;
+        let line_number = index + 1;
+        match line.split_whitespace().next() {
+            Some("p") => {
+                *num_nodes = parse_field(&line, 2, path, line_number)?;
+            },
+            Some("e") | Some("a") => {
+                let (node1, node2) = parse_two_one_based_nodes(&line, 1, path, line_number)?;
+                insert_data_graph_edge(edges, node1, node2);
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+pub fn create_data_graph(edges: &HashSet<(i32, i32)>, num_nodes: usize) -> DataGraph {
+    let mut node_ids: Vec<i32> = (0..num_nodes as i32).collect();
+    for &(node1, node2) in edges {
+        node_ids.push(node1);
+        node_ids.push(node2);
+    }
+    node_ids.sort();
+    node_ids.dedup();
+    let dense_ids: HashMap<i32, u32> = node_ids.iter().enumerate().map(|(i, node)| (*node, i as u32)).collect();
+    let mut degrees = vec![0_u32; node_ids.len()];
+    for (node1, node2) in edges {
+        degrees[dense_ids[node1] as usize] += 1;
+        degrees[dense_ids[node2] as usize] += 1;
+    }
+    let mut offsets = vec![0_u32; node_ids.len() + 1];
+    for i in 0..node_ids.len() {
+        offsets[i + 1] = offsets[i] + degrees[i];
+    }
+    let mut next = offsets.clone();
+    let mut neighbours = vec![0_u32; offsets[node_ids.len()] as usize];
+    for (node1, node2) in edges {
+        let (dense1, dense2) = (dense_ids[node1], dense_ids[node2]);
+        neighbours[next[dense1 as usize] as usize] = dense2;
+        next[dense1 as usize] += 1;
+        neighbours[next[dense2 as usize] as usize] = dense1;
+        next[dense2 as usize] += 1;
+    }
+    for i in 0..node_ids.len() {
+        neighbours[offsets[i] as usize..offsets[i + 1] as usize].sort();
+    }
+    DataGraph {
+        offsets: offsets,
+        neighbours: neighbours,
+        node_ids: node_ids,
+    }
+}
+
+// The length and modification time (in nanoseconds since the epoch) of the source, which the
+// cache has to match exactly: a copy that keeps an older time, or an edit within the
+// timestamp resolution that changes the size, is not mistaken for the cached graph.
+fn get_data_graph_source_stamp(path: &str) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos() as u64))
+}
+
+// The same file read as another format is another graph, so the cache records the format.
+fn data_graph_format_code(format: DataGraphFormat) -> u64 {
+    match format {
+        DataGraphFormat::Snap => 0,
+        DataGraphFormat::Lg => 1,
+        DataGraphFormat::MatrixMarket => 2,
+        DataGraphFormat::Dimacs => 3,
+    }
+}
+
+// Cache layout, all little endian: the 8 byte magic, the format code, the source length and
+// modification time, the number of vertices n and of neighbour entries as u64, then offsets (n + 1 u32), neighbours (u32)
+// and node ids (i32). Every array starts 4-byte aligned, so the file can also be mapped
+// and read in place.
+fn write_data_graph_cache(data_graph: &DataGraph, format: DataGraphFormat, source_stamp: (u64, u64), cache_path: &str) {
+    let mut bytes = Vec::with_capacity(48 + 4 * (data_graph.offsets.len() + data_graph.neighbours.len() + data_graph.node_ids.len()));
+    bytes.extend_from_slice(DATA_GRAPH_CACHE_MAGIC);
+    bytes.extend_from_slice(&data_graph_format_code(format).to_le_bytes());
+    bytes.extend_from_slice(&source_stamp.0.to_le_bytes());
+    bytes.extend_from_slice(&source_stamp.1.to_le_bytes());
+    bytes.extend_from_slice(&(data_graph.num_nodes() as u64).to_le_bytes());
+    bytes.extend_from_slice(&(data_graph.neighbours.len() as u64).to_le_bytes());
+    for offset in data_graph.offsets.iter() {
+        bytes.extend_from_slice(&offset.to_le_bytes());
+    }
+    for neighbour in data_graph.neighbours.iter() {
+        bytes.extend_from_slice(&neighbour.to_le_bytes());
+    }
+    for node_id in data_graph.node_ids.iter() {
+        bytes.extend_from_slice(&node_id.to_le_bytes());
+    }
+    // a read-only data directory only costs the cache, not the run
+    let _ = fs::write(cache_path, bytes);
+}
+
+fn read_data_graph_cache(cache_path: &str, format: DataGraphFormat, source_stamp: (u64, u64)) -> Option<DataGraph> {
+    let bytes = fs::read(cache_path).ok()?;
+    if bytes.len() < 48 || &bytes[..8] != DATA_GRAPH_CACHE_MAGIC {
+        return None;
+    }
+    let header: Vec<u64> = bytes[8..48].chunks_exact(8).map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap())).collect();
+    if header[0] != data_graph_format_code(format) || (header[1], header[2]) != source_stamp {
+        return None;
+    }
+    let num_nodes = header[3] as usize;
+    let num_neighbours = header[4] as usize;
+    if bytes.len() != 48 + 4 * (num_nodes + 1 + num_neighbours + num_nodes) {
+        return None;
+    }
+    let words: Vec<[u8; 4]> = bytes[48..].chunks_exact(4).map(|chunk| chunk.try_into().unwrap()).collect();
+    let offsets = words[..num_nodes + 1].iter().map(|word| u32::from_le_bytes(*word)).collect();
+    let neighbours = words[num_nodes + 1..num_nodes + 1 + num_neighbours].iter().map(|word| u32::from_le_bytes(*word)).collect();
+    let node_ids = words[num_nodes + 1 + num_neighbours..].iter().map(|word| i32::from_le_bytes(*word)).collect();
+    Some(DataGraph {
+        offsets: offsets,
+        neighbours: neighbours,
+        node_ids: node_ids,
+    })
+}
+
+// The cost estimator reads the data graph as a plain "u v" edge list, whatever format it
+// was loaded from.
+pub fn write_data_graph_edge_list(data_graph: &DataGraph, path: String) -> Result<(), OptimizerError> {
+    let mut lines = vec![];
+    for node1 in 0..data_graph.num_nodes() as u32 {
+        for &node2 in data_graph.neighbours(node1) {
+            if node1 < node2 {
+                lines.push(data_graph.node_ids[node1 as usize].to_string() + " " + &data_graph.node_ids[node2 as usize].to_string());
+            }
+        }
+    }
+    write_to_file(lines, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes contents to a fresh file in the temporary directory, with the given extension.
+    fn fixture(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("research_{}_{}", std::process::id(), name));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(path.clone() + DATA_GRAPH_CACHE_EXTENSION);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn remove_fixture(path: &str) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(path.to_string() + DATA_GRAPH_CACHE_EXTENSION);
+    }
+
+    // Edges in the original node ids, each once with the smaller id first.
+    fn edge_set(data_graph: &DataGraph) -> HashSet<(i32, i32)> {
+        let mut edges = HashSet::new();
+        for node1 in 0..data_graph.num_nodes() as u32 {
+            for &node2 in data_graph.neighbours(node1) {
+                let (id1, id2) = (data_graph.node_ids[node1 as usize], data_graph.node_ids[node2 as usize]);
+                edges.insert((cmp::min(id1, id2), cmp::max(id1, id2)));
+            }
+        }
+        edges
+    }
+
+    fn load_fixture(name: &str, contents: &str, format: DataGraphFormat) -> Result<DataGraph, OptimizerError> {
+        let path = fixture(name, contents);
+        let data_graph = load_data_graph(&path, format);
+        remove_fixture(&path);
+        data_graph
+    }
+
+    #[test]
+    fn every_format_reads_the_same_triangle_with_a_tail() {
+        let expected: HashSet<(i32, i32)> = [(0, 1), (0, 2), (1, 2), (2, 3)].into_iter().collect();
+        let fixtures = [
+            ("snap.txt", "# triangle with a tail\n0 1\n1 2\n2 0\n% comment\n2 3\n3 2\n", DataGraphFormat::Snap),
+            ("graph.lg", "t 1 4\nv 0 1\nv 1 1\nv 2 1\nv 3 1\ne 0 1 0\ne 1 2 0\ne 0 2 0\ne 2 3 0\n", DataGraphFormat::Lg),
+            ("graph.mtx", "%%MatrixMarket matrix coordinate pattern symmetric\n4 4 4\n2 1\n3 2\n3 1\n4 3\n", DataGraphFormat::MatrixMarket),
+            ("graph.dimacs", "c triangle with a tail\np edge 4 4\ne 1 2\ne 2 3\ne 1 3\ne 3 4\n", DataGraphFormat::Dimacs),
+        ];
+        for (name, contents, format) in fixtures {
+            let data_graph = load_fixture(name, contents, format).unwrap();
+            assert_eq!(data_graph.num_nodes(), 4, "{}", name);
+            assert_eq!(edge_set(&data_graph), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn one_based_formats_reject_node_zero() {
+        let fixtures = [
+            ("zero.mtx", "%%MatrixMarket matrix coordinate pattern general\n3 3 2\n1 2\n0 3\n", DataGraphFormat::MatrixMarket, 4, 1),
+            ("zero.dimacs", "p edge 3 2\ne 1 2\ne 3 0\n", DataGraphFormat::Dimacs, 3, 5),
+        ];
+        for (name, contents, format, expected_line, expected_column) in fixtures {
+            match load_fixture(name, contents, format) {
+                Err(OptimizerError::Parse { line, column, .. }) => assert_eq!((line, column), (expected_line, expected_column), "{}", name),
+                other => panic!("{}: expected a parse error, got {:?}", name, other.map(|data_graph| data_graph.num_nodes())),
+            }
+        }
+    }
+
+    #[test]
+    fn format_follows_the_extension_unless_overridden() {
+        assert_eq!(data_graph_format_from_path("mico.lg"), DataGraphFormat::Lg);
+        assert_eq!(data_graph_format_from_path("graphs/web.mtx"), DataGraphFormat::MatrixMarket);
+        assert_eq!(data_graph_format_from_path("road.gr"), DataGraphFormat::Dimacs);
+        assert_eq!(data_graph_format_from_path("edges.txt"), DataGraphFormat::Snap);
+        assert_eq!(data_graph_format_from_path("graph.unknown"), DataGraphFormat::Snap);
+        assert_eq!(data_graph_format_from_path("graph"), DataGraphFormat::Snap);
+        let args: Vec<String> = ["research", "patterns.txt", "--graph", "web.mtx", "--format", "dimacs"].iter().map(|arg| arg.to_string()).collect();
+        assert_eq!(get_data_graph_source(&args).unwrap(), ("web.mtx".to_string(), DataGraphFormat::Dimacs));
+        let args: Vec<String> = ["research", "patterns.txt", "--format", "csv"].iter().map(|arg| arg.to_string()).collect();
+        assert!(get_data_graph_source(&args).is_err());
+    }
+
+    #[test]
+    fn cache_is_reused_only_for_the_same_source() {
+        let path = fixture("cached.txt", "0 1\n1 2\n");
+        let cache_path = path.clone() + DATA_GRAPH_CACHE_EXTENSION;
+        let data_graph = load_data_graph(&path, DataGraphFormat::Snap).unwrap();
+        let source_stamp = get_data_graph_source_stamp(&path).unwrap();
+        let cached = read_data_graph_cache(&cache_path, DataGraphFormat::Snap, source_stamp).unwrap();
+        assert_eq!((&cached.offsets, &cached.neighbours, &cached.node_ids), (&data_graph.offsets, &data_graph.neighbours, &data_graph.node_ids));
+        assert!(read_data_graph_cache(&cache_path, DataGraphFormat::Lg, source_stamp).is_none());
+        assert!(read_data_graph_cache(&cache_path, DataGraphFormat::Snap, (source_stamp.0 + 1, source_stamp.1)).is_none());
+        assert!(read_data_graph_cache(&cache_path, DataGraphFormat::Snap, (source_stamp.0, source_stamp.1 - 1)).is_none());
+        // a rewritten source is parsed again
+        fs::write(&path, "0 1\n1 2\n2 0\n").unwrap();
+        let reloaded = load_data_graph(&path, DataGraphFormat::Snap).unwrap();
+        assert_eq!(reloaded.num_edges(), 3);
+        remove_fixture(&path);
+    }
+}
@@ -0,0 +1,372 @@
+use egg::*;
+use crate::*;
+use std::collections::HashMap;
+
+
+// Basis conversion between subgraph, embedding and homomorphism counts of edge-only patterns:
+//   sub(P) = inj(P) / |Aut(P)|
+//   hom(P) = sum over independent partitions p of V(P) of inj(P/p)
+//   inj(P) = sum over independent partitions p of V(P) of mu(p) * hom(P/p)
+// where P/p merges every block of p into one vertex and mu(p) = prod_B (-1)^(|B|-1) (|B|-1)!.
+// Partitions with an edge inside a block give self loops, which never map into a simple
+// data graph, so only independent partitions take part. A Hom pattern with <> pairs is only
+// partially injective, its maps never merge a <> pair, so no block may hold one either and
+// hom_to_inj turns it into fully injective counts. An anti-edge inside a block holds (no
+// vertex is its own neighbour), an edge and an anti-edge between the same two blocks can't.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubToInj {
+    pub num: Var,
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for SubToInj {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let num_string = format!("{}", egraph.id_to_expr(subst[self.num]));
+        let provenance_id = subst[self.provenance];
+        let pattern_id = subst[self.pattern];
+        let pattern_string = format!("{}", egraph.id_to_expr(pattern_id));
+        let sub_pattern_string = "sub_to_inj ".to_string() + &num_string + " " + &format!("{}", egraph.id_to_expr(provenance_id)) + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&sub_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(sub_pattern_string);
+        if !is_edge_only_pattern(&pattern_string) {
+            return vec![];
+        }
+        // the division is exact on every data graph, div_count cancels it when the
+        // coefficient already carries the whole automorphism group
+        let (_, group_size) = canonical_label_matrix(&pattern_to_label_matrix(&pattern_string), None);
+        let inj_id = egraph.add(SimpleLanguage::Inj([provenance_id, pattern_id]));
+        let count_id = egraph.add(SimpleLanguage::Count([subst[self.num], inj_id]));
+        let group_size_id = egraph.add(SimpleLanguage::Num(group_size as i64));
+        let div_id = egraph.add(SimpleLanguage::Div([count_id, group_size_id]));
+        if union_with_reason(egraph, matched_id, div_id, rule_name) {
+            vec![div_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjToSub {
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for InjToSub {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let pattern_id = subst[self.pattern];
+        let pattern_string = format!("{}", egraph.id_to_expr(pattern_id));
+        let inj_pattern_string = "inj_to_sub ".to_string() + &format!("{}", egraph.id_to_expr(provenance_id)) + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&inj_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(inj_pattern_string);
+        // an anti-edge quotient can have fewer automorphisms than its edges alone
+        let group_size = find_labelled_group_size(&pattern_string, None);
+        let morph_id = egraph.add(SimpleLanguage::Morph([provenance_id, pattern_id]));
+        let num_id = egraph.add(SimpleLanguage::Num(group_size));
+        let count_id = egraph.add(SimpleLanguage::Count([num_id, morph_id]));
+        if union_with_reason(egraph, matched_id, count_id, rule_name) {
+            vec![count_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjToHom {
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for InjToHom {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let pattern_string = format!("{}", egraph.id_to_expr(subst[self.pattern]));
+        let inj_pattern_string = "inj_to_hom ".to_string() + &format!("{}", egraph.id_to_expr(provenance_id)) + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&inj_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(inj_pattern_string);
+        let quotients = find_quotient_classes(&pattern_to_label_matrix(&pattern_string));
+        let terms = quotients.into_iter().map(|(mobius_sum, _, quotient)| (mobius_sum, quotient)).collect();
+        let sum_id = match create_quotient_sum(egraph, &provenance_id, terms, true) {
+            Some(sum_id) => sum_id,
+            None => return vec![],
+        };
+        if union_with_reason(egraph, matched_id, sum_id, rule_name) {
+            vec![sum_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HomToInj {
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for HomToInj {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let pattern_string = format!("{}", egraph.id_to_expr(subst[self.pattern]));
+        let hom_pattern_string = "hom_to_inj ".to_string() + &format!("{}", egraph.id_to_expr(provenance_id)) + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&hom_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(hom_pattern_string);
+        let quotients = find_quotient_classes(&pattern_to_label_matrix(&pattern_string));
+        let terms = quotients.into_iter().map(|(_, multiplicity, quotient)| (multiplicity, quotient)).collect();
+        let sum_id = match create_quotient_sum(egraph, &provenance_id, terms, false) {
+            Some(sum_id) => sum_id,
+            None => return vec![],
+        };
+        if union_with_reason(egraph, matched_id, sum_id, rule_name) {
+            vec![sum_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// Estimated steps of count_hom_term, on a random graph as dense as the data graph: every
+// summed out vertex walks the adjacency once, n + 2m, and the core is matched like
+// extend_hom does it, a vertex next to a placed one by an edge taking the average degree in
+// candidates and all n otherwise, each further edge keeping a candidate with the probability
+// of an edge and each further anti-edge with that of a non-edge.
+pub fn get_hom_cost(pattern: &str, data_graph: &DataGraph) -> f64 {
+    let matrix = pattern_to_label_matrix(pattern);
+    let num_nodes = data_graph.num_nodes() as f64;
+    let average_degree = 2.0 * data_graph.num_edges() as f64 / num_nodes.max(1.0);
+    let density = average_degree / (num_nodes - 1.0).max(1.0);
+    let (eliminated, core) = hom_elimination_order(&matrix);
+    let mut cost = eliminated.len() as f64 * (num_nodes + 2.0 * data_graph.num_edges() as f64);
+    if core.is_empty() {
+        return cost;
+    }
+    let core_matrix = induced_label_matrix(&matrix, &core);
+    let order = matching_order(&core_matrix, 0);
+    let mut partial_homs = 1.0;
+    for (position, &vertex) in order.iter().enumerate() {
+        let source = order[..position].iter().copied().find(|&placed| core_matrix[vertex][placed] == EDGE_LABEL);
+        let candidates = if source.is_some() { average_degree } else { num_nodes };
+        cost += partial_homs * candidates;
+        partial_homs *= candidates;
+        for &placed in order[..position].iter() {
+            if Some(placed) == source {
+                continue;
+            }
+            match core_matrix[vertex][placed] {
+                EDGE_LABEL => partial_homs *= density,
+                ANTI_EDGE_LABEL => partial_homs *= 1.0 - density,
+                _ => {},
+            }
+        }
+    }
+    cost
+}
+
+pub fn is_edge_only_pattern(pattern: &str) -> bool {
+    let matrix = pattern_to_label_matrix(pattern);
+    matrix.iter().all(|row| !row.contains(&ANTI_EDGE_LABEL))
+}
+
+// Restricted growth enumeration of the vertex partitions where no block contains an edge
+// or a <> pair.
+fn visit_independent_partitions(matrix: &Vec<Vec<usize>>, vertex: usize, blocks: &mut Vec<Vec<usize>>, visit: &mut dyn FnMut(&Vec<Vec<usize>>)) {
+    if vertex == matrix.len() {
+        visit(blocks);
+        return;
+    }
+    for i in 0..blocks.len() {
+        if blocks[i].iter().any(|&other| matrix[vertex][other] == EDGE_LABEL || matrix[vertex][other] == NOT_EQUAL_LABEL) {
+            continue;
+        }
+        blocks[i].push(vertex);
+        visit_independent_partitions(matrix, vertex + 1, blocks, visit);
+        blocks[i].pop();
+    }
+    blocks.push(vec![vertex]);
+    visit_independent_partitions(matrix, vertex + 1, blocks, visit);
+    blocks.pop();
+}
+
+// The pattern with every block merged into one vertex, without its <> pairs, None when two
+// blocks are joined by both an edge and an anti-edge.
+pub fn quotient_label_matrix(matrix: &Vec<Vec<usize>>, blocks: &Vec<Vec<usize>>) -> Option<Vec<Vec<usize>>> {
+    let mut quotient = vec![vec![NO_LABEL; blocks.len()]; blocks.len()];
+    for i in 0..blocks.len() {
+        for j in i+1..blocks.len() {
+            let has_label = |label: usize| blocks[i].iter().any(|&u| blocks[j].iter().any(|&v| matrix[u][v] == label));
+            let label = match (has_label(EDGE_LABEL), has_label(ANTI_EDGE_LABEL)) {
+                (true, true) => return None,
+                (true, false) => EDGE_LABEL,
+                (false, true) => ANTI_EDGE_LABEL,
+                (false, false) => continue,
+            };
+            quotient[i][j] = label;
+            quotient[j][i] = label;
+        }
+    }
+    Some(quotient)
+}
+
+fn mobius_coefficient(blocks: &Vec<Vec<usize>>) -> i64 {
+    let mut coefficient: i64 = 1;
+    for block in blocks {
+        let size = block.len() as i64;
+        let sign = if size % 2 == 0 { -1 } else { 1 };
+        coefficient *= sign * (1..size).product::<i64>();
+    }
+    coefficient
+}
+
+// Groups the independent partitions of a pattern by the isomorphism class of their
+// quotient. Returns (sum of mu, number of partitions, canonical quotient) per class.
+fn find_quotient_classes(matrix: &Vec<Vec<usize>>) -> Vec<(i64, i64, Vec<Vec<usize>>)> {
+    let mut classes: HashMap<Vec<Vec<usize>>, (i64, i64)> = HashMap::new();
+    let mut visit = |blocks: &Vec<Vec<usize>>| {
+        let quotient = match quotient_label_matrix(matrix, blocks) {
+            Some(quotient) => quotient,
+            None => return,
+        };
+        let (canonical, _) = canonical_label_matrix(&quotient, None);
+        let entry = classes.entry(canonical).or_insert((0, 0));
+        entry.0 += mobius_coefficient(blocks);
+        entry.1 += 1;
+    };
+    visit_independent_partitions(matrix, 0, &mut vec![], &mut visit);
+    let mut quotients = vec![];
+    for (canonical, (mobius_sum, multiplicity)) in classes {
+        quotients.push((mobius_sum, multiplicity, canonical));
+    }
+    quotients.sort_by_key(|(_, _, canonical)| label_matrix_to_pattern(canonical));
+    quotients
+}
+
+// The Match syntax has no way to mention a vertex without labels, which is what merging
+// vertices that only have anti-edges between them leaves behind.
+fn has_unlabelled_vertex(matrix: &Vec<Vec<usize>>) -> bool {
+    (0..matrix.len()).any(|v| matrix[v].iter().all(|&label| label == NO_LABEL))
+}
+
+pub fn create_quotient_sum(egraph: &mut EGraph<SimpleLanguage, Optimizer>, provenance_id: &Id, terms: Vec<(i64, Vec<Vec<usize>>)>, is_hom: bool) -> Option<Id> {
+    if terms.iter().any(|(_, quotient)| has_unlabelled_vertex(quotient)) {
+        return None;
+    }
+    let mut sum_terms = vec![];
+    for (coefficient, quotient) in terms {
+        if coefficient == 0 {
+            continue;
+        }
+        let quotient_string = label_matrix_to_pattern(&quotient);
+        if is_hom {
+            egraph.analysis.register_hom(quotient_string.clone());
+        }
+        else {
+            egraph.analysis.register_pattern(quotient_string.clone());
+        }
+        let quotient_recexpr: RecExpr<SimpleLanguage> = quotient_string.parse().unwrap();
+        let quotient_id = egraph.add_expr(&quotient_recexpr);
+        let basis_id = if is_hom {
+            egraph.add(SimpleLanguage::Hom([*provenance_id, quotient_id]))
+        }
+        else {
+            egraph.add(SimpleLanguage::Inj([*provenance_id, quotient_id]))
+        };
+        sum_terms.push((coefficient, basis_id));
+    }
+    add_sum(egraph, sum_terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brute_force::*;
+
+    #[test]
+    fn quotient_classes_convert_between_hom_and_inj() {
+        let patterns = [
+            "(Match (-- a b) (-- b c))",
+            "(Match (-- a b) (-- a c) (-- b d) (-- c d))",
+            "(Match (-- a d) (-- b c) (-- b d) (-- c d))",
+            "(Match (-- a b) (!- a c) (-- b c) (-- c d))",
+            "(Match (-- a b) (-- b c) (!- a c) (!- b d) (-- c d))",
+        ];
+        for pattern in patterns {
+            let matrix = pattern_to_label_matrix(pattern);
+            let (inj_egraph, inj_root, _) = run_rules(&format!("(Inj (Pi 0) {})", pattern), &["inj_to_hom"]);
+            let (hom_egraph, hom_root, _) = run_rules(&format!("(Hom (Pi 0) {})", pattern), &["hom_to_inj"]);
+            assert!(inj_egraph[inj_root].nodes.len() > 1 && hom_egraph[hom_root].nodes.len() > 1, "{}", pattern);
+            for adjacency in test_graphs() {
+                check_class(&inj_egraph, inj_root, count_maps(&matrix, &adjacency, true), &adjacency);
+                check_class(&hom_egraph, hom_root, count_maps(&matrix, &adjacency, false), &adjacency);
+            }
+        }
+    }
+
+    #[test]
+    fn sub_and_inj_convert_without_bliss() {
+        let patterns = [
+            "(Match (-- a b) (-- a c) (-- a d))",
+            "(Match (-- a b) (-- a c) (-- b d) (-- c d))",
+            "(Match (-- a b) (-- a c) (-- b c) (-- c d))",
+        ];
+        for pattern in patterns {
+            let matrix = pattern_to_label_matrix(pattern);
+            let (sub_egraph, sub_root, sub_statistics) = run_rules(&format!("(Count 1 (Morph (Pi 0) {}))", pattern), &["sub_to_inj"]);
+            let (inj_egraph, inj_root, inj_statistics) = run_rules(&format!("(Inj (Pi 0) {})", pattern), &["inj_to_sub"]);
+            assert_eq!(sub_statistics.applied, vec![("sub_to_inj".to_string(), 1)], "{}", pattern);
+            assert_eq!(inj_statistics.applied, vec![("inj_to_sub".to_string(), 1)], "{}", pattern);
+            for adjacency in test_graphs() {
+                check_class(&sub_egraph, sub_root, count_subgraphs(&matrix, &adjacency), &adjacency);
+                check_class(&inj_egraph, inj_root, count_maps(&matrix, &adjacency, true), &adjacency);
+            }
+        }
+    }
+
+    #[test]
+    fn inj_to_sub_divides_by_the_labelled_group() {
+        let pattern = "(Match (-- a b) (!- a c) (-- b c) (-- c d))";
+        let (egraph, root, _) = run_rules(&format!("(Inj (Pi 0) {})", pattern), &["inj_to_sub"]);
+        let coefficients: Vec<i64> = egraph[root].nodes.iter().filter_map(|node| match node {
+            SimpleLanguage::Count([num, _]) => find_num(&egraph, *num),
+            _ => None,
+        }).collect();
+        assert_eq!(coefficients, vec![1]);
+        for adjacency in test_graphs() {
+            check_class(&egraph, root, count_maps(&pattern_to_label_matrix(pattern), &adjacency, true), &adjacency);
+        }
+    }
+
+    #[test]
+    fn hom_terms_match_brute_force_counts() {
+        let patterns = [
+            "(Match (-- a b) (-- a c) (-- a d) (-- b e))",
+            "(Match (-- a b) (-- a c) (-- b c) (-- c d))",
+            "(Match (-- a c) (-- a d) (-- b c) (-- b d))",
+            "(Match (-- a b) (!- a c) (-- b c) (-- c d))",
+            "(Match (-- a b) (-- b c) (<> a c))",
+            "(Match (-- a b) (-- a c) (-- b c) (!- c d) (<> a d))",
+        ];
+        for adjacency in test_graphs() {
+            let data_graph = to_data_graph(&adjacency);
+            for pattern in patterns {
+                let count = count_hom_term(&data_graph, &format!("(Hom {})", pattern));
+                assert_eq!(count, count_maps(&pattern_to_label_matrix(pattern), &adjacency, false), "{}", pattern);
+            }
+        }
+    }
+}
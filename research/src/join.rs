@@ -0,0 +1,161 @@
+use egg::*;
+use crate::*;
+
+
+// A pattern with a cut vertex c falls apart into sides A and B that only share c. Anchoring
+// both at c, the per-vertex local counts multiply into the pairs of embeddings that agree on
+// c, and a pair is an embedding of P unless the two sides overlap elsewhere, in which case
+// it is an embedding of the pattern P/M that merges the overlapping vertices M:
+//   |Aut_c(A)| |Aut_c(B)| Join(A, B) = inj(P) + sum over non-empty matchings M of inj(P/M)
+//   sub(P) = (|Aut_c(A)| |Aut_c(B)| Join(A, B) - sum_Q m(Q) |Aut(Q)| sub(Q)) / |Aut(P)|
+// The local counts only match the two sides, so trees and other sparse 6-7 vertex
+// patterns are priced by their pieces instead of the whole pattern.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CutVertexJoin {
+    pub provenance: Var,
+    pub pattern: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for CutVertexJoin {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let provenance_string = format!("{}", egraph.id_to_expr(provenance_id));
+        let pattern_string = format!("{}", egraph.id_to_expr(subst[self.pattern]));
+        let join_pattern_string = "join ".to_string() + &provenance_string + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&join_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(join_pattern_string);
+        let matrix = pattern_to_label_matrix(&pattern_string);
+        if find_label_components(&matrix).len() > 1 {
+            return vec![];
+        }
+        let (cut_vertex, first, rest) = match find_cut_vertex_split(&matrix) {
+            Some(split) => split,
+            None => return vec![],
+        };
+        let (_, group_size) = canonical_label_matrix(&matrix, None);
+        let (first_id, first_group_size) = add_anchored_side(egraph, provenance_id, &provenance_string, &matrix, cut_vertex, &first);
+        let (rest_id, rest_group_size) = add_anchored_side(egraph, provenance_id, &provenance_string, &matrix, cut_vertex, &rest);
+        let join_id = egraph.add(SimpleLanguage::Join([first_id, rest_id]));
+        let mut terms = vec![((first_group_size * rest_group_size) as i64, join_id)];
+        for (multiplicity, quotient, quotient_group_size) in find_merged_patterns(&matrix, &first, &rest) {
+            let quotient_id = add_morph_term(egraph, provenance_id, &quotient);
+            terms.push((-((multiplicity * quotient_group_size) as i64), quotient_id));
+        }
+        let sum_id = match add_sum(egraph, terms) {
+            Some(sum_id) => sum_id,
+            None => return vec![],
+        };
+        let group_size_id = egraph.add(SimpleLanguage::Num(group_size as i64));
+        let div_id = egraph.add(SimpleLanguage::Div([sum_id, group_size_id]));
+        if union_with_reason(egraph, matched_id, div_id, rule_name) {
+            vec![div_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+// (Local provenance a side) for the side made of the cut vertex and the given vertices,
+// with the stabilizer of the cut vertex in the side's automorphism group. The side is
+// marked expanded for local_morph: count_join_term matches both operands of a Join as
+// anchored patterns, so their e-classes must not take a Sum that could be extracted instead.
+fn add_anchored_side(egraph: &mut EGraph<SimpleLanguage, Optimizer>, provenance_id: Id, provenance_string: &str, matrix: &Vec<Vec<usize>>, cut_vertex: usize, side: &Vec<usize>) -> (Id, u64) {
+    let mut vertices = vec![cut_vertex];
+    vertices.extend(side.iter());
+    // the canonical anchored form puts the anchor first
+    let (side_matrix, anchored_group_size) = canonical_label_matrix(&induced_label_matrix(matrix, &vertices), Some(0));
+    let side_string = label_matrix_to_pattern(&side_matrix);
+    egraph.analysis.register_pattern(side_string.clone());
+    let side_recexpr: RecExpr<SimpleLanguage> = side_string.parse().unwrap();
+    let side_id = egraph.add_expr(&side_recexpr);
+    let anchor_string = number_to_alphabet(1);
+    // the key LocalMorph checks, built from the same printed forms
+    egraph.analysis.mark_expanded(provenance_string.to_string() + " " + &anchor_string + " " + &format!("{}", egraph.id_to_expr(side_id)));
+    let anchor_id = egraph.add(SimpleLanguage::Symbol(Symbol::from(anchor_string)));
+    (egraph.add(SimpleLanguage::Local([provenance_id, anchor_id, side_id])), anchored_group_size)
+}
+
+// The first vertex whose removal disconnects the labelled pairs, with the component of the
+// rest holding the smallest vertex on one side and the other components on the other.
+fn find_cut_vertex_split(matrix: &Vec<Vec<usize>>) -> Option<(usize, Vec<usize>, Vec<usize>)> {
+    for cut_vertex in 0..matrix.len() {
+        let others: Vec<usize> = (0..matrix.len()).filter(|&vertex| vertex != cut_vertex).collect();
+        let components = find_label_components(&induced_label_matrix(matrix, &others));
+        if components.len() < 2 {
+            continue;
+        }
+        let first = components[0].iter().map(|&position| others[position]).collect();
+        let rest = components[1..].concat().iter().map(|&position| others[position]).collect();
+        return Some((cut_vertex, first, rest));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brute_force::*;
+    use crate::plan::count_plan_term;
+
+    #[test]
+    fn cut_vertex_joins_match_brute_force_counts() {
+        let patterns = [
+            "(Match (-- a b) (-- a c))",
+            "(Match (-- a b) (-- b c) (-- c d))",
+            "(Match (-- a d) (-- b c) (-- b d) (-- c d))",
+            "(Match (-- a b) (-- a c) (-- a d) (-- a e) (-- b c) (-- d e))",
+            "(Match (-- a b) (!- a c) (-- b c) (-- b d))",
+        ];
+        for pattern in patterns {
+            let (egraph, root, _) = run_rules(&format!("(Morph (Pi 0) {})", pattern), &["cut_vertex_join"]);
+            assert!(egraph[root].nodes.len() > 1, "{}", pattern);
+            for adjacency in test_graphs() {
+                check_class(&egraph, root, count_subgraphs(&pattern_to_label_matrix(pattern), &adjacency), &adjacency);
+            }
+        }
+    }
+
+    #[test]
+    fn extracted_joins_with_local_morph_match_brute_force_counts() {
+        let patterns = [
+            "(Match (-- a b) (-- b c) (-- c d))",
+            "(Match (-- a b) (-- a c) (-- a d) (-- a e) (-- b c) (-- d e))",
+        ];
+        for pattern in patterns {
+            let (mut egraph, root, _) = run_rules(&format!("(Morph (Pi 0) {})", pattern), &["cut_vertex_join", "local_morph"]);
+            // make the Join sides the most expensive patterns after the input, so a Sum in
+            // their e-classes would be extracted if there were one
+            let mut sides = HashSet::new();
+            for class in egraph.classes() {
+                for node in class.nodes.iter() {
+                    if let SimpleLanguage::Join(operands) = node {
+                        for operand in operands {
+                            for operand_node in egraph[*operand].nodes.iter() {
+                                if let SimpleLanguage::Local([_, _, side]) = operand_node {
+                                    sides.insert(format!("{}", egraph.id_to_expr(*side)));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            assert!(!sides.is_empty(), "{}", pattern);
+            for (key, cost) in egraph.analysis.cost_map.iter_mut() {
+                *cost = if key == pattern { 1e9 } else if sides.contains(key) { 1e6 } else { 1.0 };
+            }
+            let (_, best) = Extractor::new(&egraph, NaiveCostFunction { egraph: &egraph }).find_best(root);
+            let formula = best.to_string();
+            assert!(formula.contains("(Join"), "{}", formula);
+            for adjacency in test_graphs() {
+                let data_graph = to_data_graph(&adjacency);
+                let statistics = GraphStatistics::new(&data_graph);
+                let count = evaluate_pattern_formula(&formula, &mut |key| count_plan_term(&statistics, key).unwrap()).unwrap();
+                assert_eq!(count, count_subgraphs(&pattern_to_label_matrix(pattern), &adjacency), "{}", formula);
+            }
+        }
+    }
+}
@@ -0,0 +1,368 @@
+// &Vec / &String parameters, `field: field` initializers and index loops over label
+// matrices are how this crate is written throughout.
+#![allow(clippy::ptr_arg, clippy::redundant_field_names, clippy::needless_range_loop, clippy::type_complexity)]
+
+use egg::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::time::Instant;
+
+mod cost;
+mod utils;
+mod morph;
+mod escape;
+mod sum;
+mod arith;
+mod orbit;
+mod matcher;
+mod local;
+mod hom;
+mod graph;
+mod optimizer;
+mod plan;
+mod error;
+mod validate;
+mod component;
+mod join;
+mod formula;
+mod statistics;
+mod complement;
+mod not_equal;
+mod schedule;
+#[cfg(test)]
+mod brute_force;
+
+use crate::cost::*;
+use crate::utils::*;
+use crate::morph::*;
+use crate::escape::*;
+use crate::sum::*;
+use crate::arith::*;
+use crate::orbit::*;
+use crate::matcher::*;
+use crate::local::*;
+use crate::hom::*;
+use crate::graph::*;
+use crate::optimizer::*;
+use crate::error::*;
+use crate::validate::*;
+use crate::component::*;
+use crate::join::*;
+use crate::formula::*;
+use crate::statistics::*;
+use crate::complement::*;
+use crate::not_equal::*;
+use crate::schedule::*;
+
+pub use crate::graph::{DataGraph, DataGraphFormat, create_data_graph, data_graph_format_from_path, get_data_graph_source, load_data_graph};
+pub use crate::plan::{Config, InputFormula, Pattern, Plan};
+pub use crate::error::OptimizerError;
+pub use crate::matcher::count_join_term;
+pub use crate::matcher::count_complement_term;
+pub use crate::matcher::count_hom_term;
+pub use crate::matcher::count_pattern_term;
+pub use crate::formula::evaluate_const_formula;
+pub use crate::statistics::GraphStatistics;
+pub use crate::schedule::{Phase, PhaseStatistics, get_default_phases, get_staged_phases, read_phases};
+pub use crate::utils::{CountingSemantics, initialize_thread_pool};
+
+
+define_language! {
+    enum SimpleLanguage {
+        "--" = Edge([Id; 2]),
+        "!-" = AntiEdge([Id; 2]),
+        "<>" = NotEqual([Id; 2]),
+        "Match" = Match(Box<[Id]>),
+        "Sum" = Sum(Box<[Id]>),
+        "Mul" = Mul([Id; 2]),
+        "Sub" = Sub([Id; 2]),
+        "Div" = Div([Id; 2]),
+        "Count" = Count([Id; 2]),
+        "Morph" = Morph([Id; 2]),
+        "Local" = Local([Id; 3]),
+        "Join" = Join([Id; 2]),
+        "Complement" = Complement([Id; 2]),
+        "Hom" = Hom([Id; 2]),
+        "Inj" = Inj([Id; 2]),
+        "Pi" = Pi(Box<[Id]>),
+        "Const" = Const([Id; 2]),
+        // Const formulas, see formula.rs
+        "SumV" = SumV([Id; 2]),
+        "SumE" = SumE([Id; 3]),
+        "SumT" = SumT([Id; 4]),
+        "Deg" = Deg(Id),
+        "Wedge" = Wedge(Id),
+        "TriV" = TriV(Id),
+        "TriE" = TriE([Id; 2]),
+        "C4V" = C4V(Id),
+        "Walk" = Walk([Id; 2]),
+        "Choose" = Choose([Id; 2]),
+        "Times" = Times([Id; 2]),
+        "Minus" = Minus([Id; 2]),
+        "Quot" = Quot([Id; 2]),
+        Num(i64),
+        Symbol(Symbol),
+    }
+}
+
+
+fn get_static_rewrite_rules() -> Vec<Rewrite<SimpleLanguage, Optimizer>> {
+    let mut rules = get_escape_rewrite_rules();
+    rules.extend(vec![
+        rewrite!("morph"; "(Morph ?provenance ?pattern)" => { Morph {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("disconnected_morph"; "(Morph ?provenance ?pattern)" => { DisconnectedMorph {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("cut_vertex_join"; "(Morph ?provenance ?pattern)" => { CutVertexJoin {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("complement_morph"; "(Morph ?provenance ?pattern)" => { ComplementMorph {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        // escape and dedup rules only match Morph, so anchored counts are only ever
+        // rewritten by local_morph, which keeps the anchor orbit fixed
+        rewrite!("local_morph"; "(Local ?provenance ?anchor ?pattern)" => { LocalMorph {
+            provenance: "?provenance".parse().unwrap(),
+            anchor: "?anchor".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("sub_to_inj"; "(Count ?num (Morph ?provenance ?pattern))" => { SubToInj {
+            num: "?num".parse().unwrap(),
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("inj_to_sub"; "(Inj ?provenance ?pattern)" => { InjToSub {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("inj_to_hom"; "(Inj ?provenance ?pattern)" => { InjToHom {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("hom_to_inj"; "(Hom ?provenance ?pattern)" => { HomToInj {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        rewrite!("not_equal_expansion"; "(Hom ?provenance ?pattern)" => { NotEqualExpansion {
+            provenance: "?provenance".parse().unwrap(),
+            pattern: "?pattern".parse().unwrap(),
+        }}),
+        // Sums are canonical by construction (see sum.rs), so there are no commutativity or
+        // associativity rules, only flattening, deduplication and distribution
+        Rewrite::new("sum_flatten", SumSearcher { sum: "?sum".parse().unwrap() }, SumFlatten {
+            sum: "?sum".parse().unwrap(),
+        }).unwrap(),
+        Rewrite::new("sum_dedup", SumSearcher { sum: "?sum".parse().unwrap() }, SumDedup {
+            sum: "?sum".parse().unwrap(),
+        }).unwrap(),
+        rewrite!("count_dist"; "(Count ?num ?terms)" => { CountDist {
+            num: "?num".parse().unwrap(),
+            terms: "?terms".parse().unwrap(),
+        }}),
+        rewrite!("count_mult"; "(Count ?num1 (Count ?num2 ?rest))" => { CountMult {
+            num1: "?num1".parse().unwrap(),
+            num2: "?num2".parse().unwrap(),
+            rest: "?rest".parse().unwrap(),
+        }}),
+        rewrite!("sub_to_sum"; "(Sub ?l ?r)" => { SubToSum {
+            l: "?l".parse().unwrap(),
+            r: "?r".parse().unwrap(),
+        }}),
+        rewrite!("mul_switch"; "(Mul ?l ?r)" => "(Mul ?r ?l)"),
+        rewrite!("mul_count"; "(Mul (Count ?num ?l) ?r)" => "(Count ?num (Mul ?l ?r))"),
+        rewrite!("mul_dist"; "(Mul ?l ?terms)" => { MulDist {
+            l: "?l".parse().unwrap(),
+            terms: "?terms".parse().unwrap(),
+        }}),
+        rewrite!("count_div"; "(Count ?num (Div ?rest ?divisor))" => { DivCount {
+            num: "?num".parse().unwrap(),
+            rest: "?rest".parse().unwrap(),
+            divisor: "?divisor".parse().unwrap(),
+        }}),
+        rewrite!("div_count"; "(Div (Count ?num ?rest) ?divisor)" => { DivCount {
+            num: "?num".parse().unwrap(),
+            rest: "?rest".parse().unwrap(),
+            divisor: "?divisor".parse().unwrap(),
+        }}),
+        rewrite!("div_sum"; "(Div ?terms ?divisor)" => { DivSum {
+            terms: "?terms".parse().unwrap(),
+            divisor: "?divisor".parse().unwrap(),
+        }}),
+        rewrite!("div_one"; "(Div ?rest 1)" => "?rest"),
+    ]);
+    rules
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CountMult {
+    num1: Var,
+    num2: Var,
+    rest: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for CountMult {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let num1 = subst[self.num1];
+        let num2 = subst[self.num2];
+        let rest = subst[self.rest];
+
+        let num1_recexpr = egraph.id_to_expr(num1);
+        let num2_recexpr = egraph.id_to_expr(num2);
+        let num1_string = format!("{}", num1_recexpr);
+        let num2_string = format!("{}", num2_recexpr);
+        let num1_i32: i64 = num1_string.parse().unwrap();
+        let num2_i32: i64 = num2_string.parse().unwrap();
+        let new_num = num1_i32.checked_mul(num2_i32).unwrap_or(i64::MAX);
+        if new_num == i64::MAX {
+            return vec![];
+        }
+        let new_num_id = egraph.add(SimpleLanguage::Num(new_num));
+        let count_id = egraph.add(SimpleLanguage::Count([new_num_id, rest]));
+        let new_pattern = count_id;
+        if union_with_reason(egraph, matched_id, new_pattern, rule_name) {
+            vec![new_pattern]
+        } else {
+            vec![]
+        }
+    }   
+}
+
+
+// The input indices of a provenance "(Pi 0 2 5)".
+fn process_string(s: &str) -> Result<Vec<i32>, OptimizerError> {
+    if !s.starts_with("(Pi ") || !s.ends_with(')') {
+        return Err(OptimizerError::parse(s, 1, 1, "expected a (Pi ...) provenance"));
+    }
+    let trimmed = &s[4..s.len()-1];
+    let mut numbers = vec![];
+    for index in 0..trimmed.split_whitespace().count() {
+        // columns are counted in the whole provenance, past "(Pi "
+        numbers.push(parse_field::<i32>(trimmed, index, s, 1).map_err(|error| match error {
+            OptimizerError::Parse { path, line, column, message } => OptimizerError::Parse { path, line, column: column + 4, message },
+            error => error,
+        })?);
+    }
+    Ok(numbers)
+}
+
+fn get_merged_provenance(p1: &str, p2: &str) -> Result<String, OptimizerError> {
+    let mut numbers: Vec<i32> = process_string(p1)?;
+    numbers.extend(process_string(p2)?);
+    let unique_numbers: HashSet<_> = numbers.into_iter().collect(); // Remove duplicates
+    let mut sorted_numbers: Vec<_> = unique_numbers.into_iter().collect(); // Convert back to Vec
+    sorted_numbers.sort(); // Sort the numbers
+    Ok(sorted_numbers.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(" "))
+}
+
+// Reads an input file, one pattern per line (see read_input_patterns_from_file).
+pub fn read_patterns(filename: &str) -> Result<Vec<Pattern>, OptimizerError> {
+    let (patterns, semantics) = read_input_patterns_from_file(filename)?;
+    Ok(patterns.iter().zip(semantics).map(|(pattern, semantics)| Pattern::new(pattern, semantics)).collect())
+}
+
+// Every connected pattern on num_nodes vertices, generated by bliss. Has to be called
+// before optimize, the census request must be the first message bliss sees.
+pub fn generate_census(num_nodes: usize, vertex_induced: bool) -> Result<Vec<Pattern>, OptimizerError> {
+    create_directory(DIRECTORY_PATH)?;
+    create_pipes()?;
+    Ok(generate_census_patterns(num_nodes, vertex_induced)?.iter()
+        .map(|pattern| Pattern::new(pattern, CountingSemantics::Subgraphs))
+        .collect())
+}
+
+// Finds the cheapest set of patterns to match against data_graph from which every input
+// count can be assembled. Expects bliss and the cost estimator to be listening on their pipes.
+pub fn optimize(patterns: &[Pattern], data_graph: &DataGraph, config: &Config) -> Result<Plan, OptimizerError> {
+    if patterns.is_empty() {
+        return Err(OptimizerError::Argument("no patterns".to_string()));
+    }
+    let semantics: Vec<CountingSemantics> = patterns.iter().map(|pattern| pattern.semantics).collect();
+    // patterns built with Pattern::new never went through read_patterns, so they get the same
+    // checks here, e.g. a Homomorphisms vertex that only appears in <> atoms is rejected
+    let patterns: Vec<String> = patterns.iter().enumerate()
+        .map(|(index, pattern)| validate_pattern(&pattern.pattern, 0, "patterns", index + 1, pattern.semantics == CountingSemantics::Homomorphisms))
+        .collect::<Result<_, _>>()?;
+    create_directory(DIRECTORY_PATH)?;
+    create_pipes()?;
+    let mut optimizer = Optimizer::new(patterns.len());
+    optimizer.load_morph_memo()?;
+    // bliss only sees edges, so its labelling is not canonical once there are anti-edges or
+    // <> pairs. Every pattern enters the e-graph in the native canonical form of orbit.rs,
+    // which is what the escape catalogue and the other rewrites are keyed on.
+    let bliss_patterns: Vec<String> = patterns.iter().zip(semantics.iter())
+        .filter(|(_, &semantics)| semantics != CountingSemantics::Homomorphisms)
+        .map(|(pattern, _)| pattern.clone())
+        .collect();
+    let mut bliss_canonical_patterns = if bliss_patterns.is_empty() { vec![] } else { make_patterns_canonical(&bliss_patterns)? }.into_iter();
+    let canonical_patterns: Vec<String> = patterns.iter().zip(semantics.iter()).map(|(pattern, &semantics)| match semantics {
+        CountingSemantics::Homomorphisms => canonical_pattern_string(pattern, None),
+        _ => canonical_pattern_string(&bliss_canonical_patterns.next().unwrap(), None),
+    }).collect();
+    // let canonical_patterns = patterns;
+    let mut input_terms = vec![];
+    let mut local_inputs = vec![];
+    if config.local {
+        local_inputs = get_local_input_patterns(&canonical_patterns, &semantics)?;
+        optimizer.num_patterns = local_inputs.len();
+        for i in 0..local_inputs.len() {
+            optimizer.register_pattern(local_inputs[i].pattern.to_string());
+            input_terms.push("(Count ".to_string() + &local_inputs[i].coefficient.to_string() + " (Local (Pi " + &i.to_string() + ") " + &number_to_alphabet(1) + " " + &local_inputs[i].pattern + "))");
+        }
+    }
+    else {
+        let input_coefficients = get_input_coefficients(&canonical_patterns, &semantics)?;
+        for i in 0..patterns.len() {
+            let basis = if semantics[i] == CountingSemantics::Homomorphisms { "Hom" } else { "Morph" };
+            let pattern = "(Count ".to_string() + &input_coefficients[i].to_string() + " (" + basis + " (Pi " + &i.to_string() + ") " + &canonical_patterns[i] + "))";
+            // let pattern = "(Count 1 (Morph ".to_string() + "(Pi " + &provenance.to_string() + ") " + &canonical_patterns[i] + "))";
+            if semantics[i] == CountingSemantics::Homomorphisms {
+                optimizer.register_hom(canonical_patterns[i].to_string());
+            }
+            else {
+                optimizer.register_pattern(canonical_patterns[i].to_string());
+            }
+            input_terms.push(pattern);
+        }
+    }
+    let mut s = input_terms[0].clone();
+    if input_terms.len() > 1 {
+        s = "(Sum".to_string();
+        for input_term in input_terms.iter() {
+            s = s + " 1 " + input_term;
+        }
+        s += ")";
+    }
+    let start = Instant::now();
+    let (alt_patterns_string, optimized_cost, mut egraph, phase_statistics) = simplify(&s, optimizer, data_graph, vec![], &config.phases)?;
+    // println!("alternative patterns {}", alt_patterns_string);
+    let end = Instant::now();
+    write_to_pipe(BLISS_PIPE_NAME, "done".to_string())?;
+    let alt_patterns = parse_alt_patterns_string(alt_patterns_string)?;
+    // the formulas are extracted with the alternative patterns priced at zero
+    let pattern_costs: HashMap<String, f64> = alt_patterns.keys()
+        .map(|pattern| (pattern.clone(), *egraph.analysis.cost_map.get(pattern).unwrap_or(&0.0)))
+        .collect();
+    let formulas = if config.local {
+        get_local_patterns_formulas(&local_inputs, &alt_patterns, &mut egraph, config.explain)?
+    }
+    else {
+        get_patterns_formulas(&alt_patterns, &input_terms, &mut egraph, config.explain)?
+    };
+    let cost_directory = DIRECTORY_PATH.to_string() + COST_DIRECTORY;
+    fs::remove_dir_all(&cost_directory).map_err(|error| OptimizerError::io(&cost_directory, error))?;
+    Ok(Plan {
+        expression: s,
+        alternative_patterns: alt_patterns,
+        pattern_costs: pattern_costs,
+        cost: optimized_cost,
+        formulas: formulas,
+        generation_time: end - start,
+        phases: phase_statistics,
+    })
+}
@@ -0,0 +1,198 @@
+use egg::*;
+use crate::*;
+use std::collections::{HashMap, HashSet};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalMorph {
+    pub provenance: Var,
+    pub anchor: Var,
+    pub pattern: Var,
+}
+
+// Morph for anchored (per-vertex) counts. The anchor stays fixed while the super-patterns
+// are enumerated, so every term keeps counting the same vertex orbit.
+impl Applier<SimpleLanguage, Optimizer> for LocalMorph {
+
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let provenance_id = subst[self.provenance];
+        let anchor_id = subst[self.anchor];
+        let provenance_string = format!("{}", egraph.id_to_expr(provenance_id));
+        let anchor_string = format!("{}", egraph.id_to_expr(anchor_id));
+        let pattern_string = format!("{}", egraph.id_to_expr(subst[self.pattern]));
+        let local_pattern_string = provenance_string + " " + &anchor_string + " " + &pattern_string;
+        if egraph.analysis.is_expanded(&local_pattern_string) {
+            return vec![];
+        }
+        egraph.analysis.mark_expanded(local_pattern_string);
+        let matrix = pattern_to_label_matrix(&pattern_string);
+        let anchor = alphabet_to_number(&anchor_string) - 1;
+        let expansion = expand_label_matrix(&matrix, Some(anchor));
+        if expansion.is_empty() {
+            return vec![];
+        }
+        let mut terms = vec![];
+        for (coefficient, super_pattern) in expansion {
+            // the canonical anchored form always puts the anchor first
+            let super_pattern_string = label_matrix_to_pattern(&super_pattern);
+            egraph.analysis.register_pattern(super_pattern_string.clone());
+            let super_pattern_recexpr: RecExpr<SimpleLanguage> = super_pattern_string.parse().unwrap();
+            let super_pattern_id = egraph.add_expr(&super_pattern_recexpr);
+            let new_anchor_id = egraph.add(SimpleLanguage::Symbol(Symbol::from(number_to_alphabet(1))));
+            let local_id = egraph.add(SimpleLanguage::Local([provenance_id, new_anchor_id, super_pattern_id]));
+            terms.push((coefficient, local_id));
+        }
+        let sum_id = match add_sum(egraph, terms) {
+            Some(sum_id) => sum_id,
+            None => return vec![],
+        };
+        if union_with_reason(egraph, matched_id, sum_id, rule_name) {
+            vec![sum_id]
+        } else {
+            vec![]
+        }
+    }
+}
+
+pub struct LocalInput {
+    pub input: usize,
+    pub vertex: usize,
+    pub pattern: String,
+    pub coefficient: i64,
+}
+
+// One anchored input per vertex orbit of every input pattern. bliss gives the orbits of
+// the edge graph; the anchored canonical form splits them further when anti-edges break
+// a symmetry.
+pub fn get_local_input_patterns(patterns: &Vec<String>, semantics: &Vec<CountingSemantics>) -> Result<Vec<LocalInput>, OptimizerError> {
+    let mut local_inputs = vec![];
+    for (input, pattern) in patterns.iter().enumerate() {
+        if semantics[input] == CountingSemantics::Homomorphisms {
+            return Err(OptimizerError::Argument(format!("{} counts homomorphisms, which have no per-vertex form", pattern)));
+        }
+        let edge_induced_pattern = convert_to_edge_induced_pattern(pattern, false);
+        let num_nodes = get_num_nodes(pattern);
+        let pattern_info = parse_input(&edge_induced_pattern, num_nodes);
+        let automorphism_info = find_automorphisms_with_bliss(&pattern_info)?;
+        let matrix = pattern_to_label_matrix(pattern);
+        let mut seen_orbits = HashSet::new();
+        for vertex in 0..num_nodes {
+            let (anchored, _) = canonical_label_matrix(&matrix, Some(vertex));
+            let local_pattern = label_matrix_to_pattern(&anchored);
+            if seen_orbits.insert((automorphism_info.orbits[vertex], local_pattern.clone())) {
+                // anchored embeddings only permute the vertices outside the anchor
+                let coefficient = match semantics[input] {
+                    CountingSemantics::Embeddings => find_labelled_group_size(&local_pattern, Some(0)),
+                    CountingSemantics::Subgraphs | CountingSemantics::Homomorphisms => 1,
+                };
+                local_inputs.push(LocalInput {
+                    input: input,
+                    vertex: vertex,
+                    pattern: local_pattern,
+                    coefficient: coefficient,
+                });
+            }
+        }
+    }
+    Ok(local_inputs)
+}
+
+pub fn parse_local_key(key: &str) -> (String, String) {
+    let trimmed = &key["(Local ".len()..key.len() - 1];
+    let (anchor, pattern) = trimmed.split_once(' ').unwrap();
+    (anchor.to_string(), pattern.to_string())
+}
+
+pub fn get_local_patterns_formulas(local_inputs: &Vec<LocalInput>, alt_patterns: &HashMap<String, i32>, egraph: &mut EGraph<SimpleLanguage, Optimizer>, explain: bool) -> Result<Vec<InputFormula>, OptimizerError> {
+    change_alt_patterns_cost(alt_patterns, &mut egraph.analysis.cost_map);
+    let mut formulas = vec![];
+    for (i, local_input) in local_inputs.iter().enumerate() {
+        let pattern = "(Count ".to_string() + &local_input.coefficient.to_string() + " (Local (Pi " + &i.to_string() + ") " + &number_to_alphabet(1) + " " + &local_input.pattern + "))";
+        let new_expr: RecExpr<SimpleLanguage> = pattern.parse().unwrap();
+        let new_root = egraph.add_expr(&new_expr);
+        let (best_cost, best) = Extractor::new(egraph, NaiveCostFunction { egraph: egraph }).find_best(new_root);
+        let map = simplify_pattern_formula(format!("{}", best))?;
+        let mut explanation = None;
+        if explain {
+            explanation = Some(get_rewrite_explanation(&new_expr, &best, egraph));
+        }
+        formulas.push(InputFormula {
+            input: local_input.input,
+            anchor: Some(local_input.vertex),
+            expression: new_expr.to_string(),
+            formula: best.to_string(),
+            terms: map,
+            cost: best_cost,
+            explanation: explanation,
+        });
+    }
+    Ok(formulas)
+}
+
+// Graphlet degree vectors: one row per data vertex, one column per (input pattern, orbit).
+pub fn print_local_count_table(formulas: &Vec<InputFormula>, data_graph: &DataGraph) -> Result<(), OptimizerError> {
+    let mut term_counts: HashMap<String, HashMap<i32, i64>> = HashMap::new();
+    let vertices = data_graph.node_ids.clone();
+    println!();
+    let header: Vec<String> = formulas.iter()
+        .map(|formula| formula.input.to_string() + ":" + &number_to_alphabet(formula.anchor.unwrap() + 1))
+        .collect();
+    println!("vertex {}", header.join(" "));
+    for vertex in vertices {
+        let mut row = vec![vertex.to_string()];
+        for formula in formulas {
+            let count = formula.evaluate(&mut |key| {
+                if !term_counts.contains_key(key) {
+                    let (anchor, pattern) = parse_local_key(key);
+                    let matrix = pattern_to_label_matrix(&pattern);
+                    term_counts.insert(key.to_string(), count_local_pattern(data_graph, &matrix, alphabet_to_number(&anchor) - 1));
+                }
+                *term_counts[key].get(&vertex).unwrap_or(&0)
+            })?;
+            row.push(count.to_string());
+        }
+        println!("{}", row.join(" "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::brute_force::*;
+
+    #[test]
+    fn local_morph_sums_to_the_global_count() {
+        let patterns = [
+            "(Match (-- a b) (-- b c))",
+            "(Match (-- a b) (-- b c) (-- c d))",
+            "(Match (-- a b) (-- a c) (-- b c) (-- c d))",
+            "(Match (-- a b) (!- a c) (-- b c) (-- b d))",
+        ];
+        for pattern in patterns {
+            let matrix = pattern_to_label_matrix(pattern);
+            for anchor in 0..matrix.len() {
+                let anchor_name = number_to_alphabet(anchor + 1);
+                let (egraph, root, statistics) = run_rules(&format!("(Local (Pi 0) {} {})", anchor_name, pattern), &["local_morph"]);
+                assert!(statistics.applied.iter().any(|(rule, count)| rule == "local_morph" && *count > 0), "{} at {}", pattern, anchor_name);
+                let terms = egraph[root].nodes.iter().find_map(|node| match node {
+                    SimpleLanguage::Sum(children) => Some(children.clone()),
+                    _ => None,
+                }).unwrap();
+                // every subgraph is counted once at each vertex of the anchor orbit
+                let orbit_size = count_automorphisms(&matrix) / count_anchored_automorphisms(&matrix, anchor);
+                for adjacency in test_graphs() {
+                    let mut per_vertex = vec![0; adjacency.len()];
+                    for pair in terms.chunks(2) {
+                        let coefficient = evaluate_class(&egraph, pair[0], &adjacency, &mut vec![]).unwrap();
+                        for (node, count) in local_counts(&egraph, pair[1], &adjacency).unwrap().into_iter().enumerate() {
+                            per_vertex[node] += coefficient * count;
+                        }
+                    }
+                    assert_eq!(per_vertex, local_counts(&egraph, root, &adjacency).unwrap(), "{} at {}", pattern, anchor_name);
+                    assert_eq!(per_vertex.iter().sum::<i64>(), count_subgraphs(&matrix, &adjacency) * orbit_size, "{} at {}", pattern, anchor_name);
+                }
+            }
+        }
+    }
+}
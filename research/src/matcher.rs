@@ -0,0 +1,287 @@
+use crate::*;
+use std::collections::HashMap;
+use rayon::prelude::*;
+
+
+// Visit order for backtracking: start from the anchor and prefer vertices that are
+// joined by an edge to an already placed vertex, so candidates come from neighbour lists.
+pub fn matching_order(matrix: &Vec<Vec<usize>>, anchor: usize) -> Vec<usize> {
+    let mut order = vec![anchor];
+    let mut placed = vec![false; matrix.len()];
+    placed[anchor] = true;
+    while order.len() < matrix.len() {
+        let next = (0..matrix.len())
+            .filter(|&v| !placed[v])
+            .find(|&v| order.iter().any(|&u| matrix[u][v] == EDGE_LABEL))
+            .unwrap_or_else(|| (0..matrix.len()).find(|&v| !placed[v]).unwrap());
+        placed[next] = true;
+        order.push(next);
+    }
+    order
+}
+
+fn is_consistent(matrix: &Vec<Vec<usize>>, order: &Vec<usize>, position: usize, candidate: u32, mapping: &Vec<Option<u32>>, data_graph: &DataGraph) -> bool {
+    let vertex = order[position];
+    for &placed in order[..position].iter() {
+        let placed_node = mapping[placed].unwrap();
+        if placed_node == candidate {
+            return false;
+        }
+        let adjacent = data_graph.has_edge(candidate, placed_node);
+        match matrix[vertex][placed] {
+            EDGE_LABEL if !adjacent => return false,
+            ANTI_EDGE_LABEL if adjacent => return false,
+            _ => {},
+        }
+    }
+    true
+}
+
+fn extend_embedding(matrix: &Vec<Vec<usize>>, order: &Vec<usize>, position: usize, mapping: &mut Vec<Option<u32>>, data_graph: &DataGraph) -> i64 {
+    if position == order.len() {
+        return 1;
+    }
+    let vertex = order[position];
+    let connected_to = order[..position].iter().find(|&&placed| matrix[vertex][placed] == EDGE_LABEL);
+    let candidates: Vec<u32> = match connected_to {
+        Some(&placed) => data_graph.neighbours(mapping[placed].unwrap()).to_vec(),
+        None => (0..data_graph.num_nodes() as u32).collect(),
+    };
+    let mut embeddings = 0;
+    for candidate in candidates {
+        if !is_consistent(matrix, order, position, candidate, mapping, data_graph) {
+            continue;
+        }
+        mapping[vertex] = Some(candidate);
+        embeddings += extend_embedding(matrix, order, position + 1, mapping, data_graph);
+    }
+    mapping[vertex] = None;
+    embeddings
+}
+
+// Native counting engine: for every data vertex v, the number of distinct subgraphs
+// matching the labelled pattern in which v plays the role of the anchor
+// (anchored embeddings divided by the automorphisms that fix the anchor).
+// Counts are keyed by the vertex ids of the input file; every anchor is an independent job.
+pub fn count_local_pattern(data_graph: &DataGraph, matrix: &Vec<Vec<usize>>, anchor: usize) -> HashMap<i32, i64> {
+    let order = matching_order(matrix, anchor);
+    let (_, anchored_group_size) = canonical_label_matrix(matrix, Some(anchor));
+    (0..data_graph.num_nodes() as u32).into_par_iter().filter_map(|node| {
+        let mut mapping = vec![None; matrix.len()];
+        mapping[anchor] = Some(node);
+        let embeddings = extend_embedding(matrix, &order, 1, &mut mapping, data_graph);
+        if embeddings > 0 {
+            Some((data_graph.node_ids[node as usize], embeddings / anchored_group_size as i64))
+        }
+        else {
+            None
+        }
+    }).collect()
+}
+
+// Count of a Morph term, the key being the pattern: distinct subgraphs with every labelled
+// pair matched, i.e. all embeddings divided by the automorphisms of the labelled pattern.
+pub fn count_pattern_term(data_graph: &DataGraph, key: &str) -> i64 {
+    let matrix = pattern_to_label_matrix(key);
+    let order = matching_order(&matrix, 0);
+    let (_, group_size) = canonical_label_matrix(&matrix, None);
+    let embeddings: i64 = (0..data_graph.num_nodes() as u32).into_par_iter().map(|node| {
+        let mut mapping = vec![None; matrix.len()];
+        mapping[order[0]] = Some(node);
+        extend_embedding(&matrix, &order, 1, &mut mapping, data_graph)
+    }).sum();
+    embeddings / group_size as i64
+}
+
+// Count of a Join term, the key being "(Join (Local a A) (Local a B))": the sum over data
+// vertices of the product of the two local counts.
+pub fn count_join_term(data_graph: &DataGraph, key: &str) -> i64 {
+    let sides: Vec<HashMap<i32, i64>> = parse_operands_string(key["(Join ".len()..].to_string()).iter().map(|side| {
+        let (anchor, pattern) = parse_local_key(side);
+        count_local_pattern(data_graph, &pattern_to_label_matrix(&pattern), alphabet_to_number(&anchor) - 1)
+    }).collect();
+    sides[0].iter().map(|(vertex, count)| count * sides[1].get(vertex).unwrap_or(&0)).sum()
+}
+
+// Complement neighbours of a data vertex: every other vertex that is not in its sorted
+// neighbour list, n - 1 - d of them, produced by merging against that list.
+fn complement_neighbours(data_graph: &DataGraph, node: u32) -> Vec<u32> {
+    let neighbours = data_graph.neighbours(node);
+    let mut complement = Vec::with_capacity(data_graph.num_nodes().saturating_sub(neighbours.len() + 1));
+    let mut next = 0;
+    for candidate in 0..data_graph.num_nodes() as u32 {
+        if next < neighbours.len() && neighbours[next] == candidate {
+            next += 1;
+        }
+        else if candidate != node {
+            complement.push(candidate);
+        }
+    }
+    complement
+}
+
+// Backtracking for a pattern on the complement of the data graph: an edge of the pattern is
+// a missing neighbour entry and an anti-edge a present one. Candidates come from the
+// shortest adjacency a placed vertex offers, its complement neighbours across a pattern edge
+// or its data graph neighbours across an anti-edge.
+fn extend_complement_embedding(matrix: &Vec<Vec<usize>>, order: &Vec<usize>, position: usize, mapping: &mut Vec<Option<u32>>, data_graph: &DataGraph) -> i64 {
+    if position == order.len() {
+        return 1;
+    }
+    let vertex = order[position];
+    let mut shortest: Option<(usize, u32, usize)> = None;
+    for &placed in order[..position].iter() {
+        let placed_node = mapping[placed].unwrap();
+        let length = match matrix[vertex][placed] {
+            EDGE_LABEL => data_graph.num_nodes().saturating_sub(data_graph.degree(placed_node) + 1),
+            ANTI_EDGE_LABEL => data_graph.degree(placed_node),
+            _ => continue,
+        };
+        if shortest.is_none_or(|(shortest_length, _, _)| length < shortest_length) {
+            shortest = Some((length, placed_node, matrix[vertex][placed]));
+        }
+    }
+    let candidates: Vec<u32> = match shortest {
+        Some((_, placed_node, EDGE_LABEL)) => complement_neighbours(data_graph, placed_node),
+        Some((_, placed_node, _)) => data_graph.neighbours(placed_node).to_vec(),
+        None => (0..data_graph.num_nodes() as u32).collect(),
+    };
+    let mut embeddings = 0;
+    'candidates: for candidate in candidates {
+        for &placed in order[..position].iter() {
+            let placed_node = mapping[placed].unwrap();
+            if placed_node == candidate {
+                continue 'candidates;
+            }
+            let adjacent = data_graph.has_edge(candidate, placed_node);
+            match matrix[vertex][placed] {
+                EDGE_LABEL if adjacent => continue 'candidates,
+                ANTI_EDGE_LABEL if !adjacent => continue 'candidates,
+                _ => {},
+            }
+        }
+        mapping[vertex] = Some(candidate);
+        embeddings += extend_complement_embedding(matrix, order, position + 1, mapping, data_graph);
+    }
+    mapping[vertex] = None;
+    embeddings
+}
+
+// Count of a Complement term, the key being "(Complement P')": the vertex-induced count of P'
+// on the complement of the data graph, matched directly on the complement adjacency.
+pub fn count_complement_term(data_graph: &DataGraph, key: &str) -> i64 {
+    let pattern = &key["(Complement ".len()..key.len() - 1];
+    let matrix = pattern_to_label_matrix(pattern);
+    let order = matching_order(&matrix, 0);
+    let (_, group_size) = canonical_label_matrix(&matrix, None);
+    let embeddings: i64 = (0..data_graph.num_nodes() as u32).into_par_iter().map(|node| {
+        let mut mapping = vec![None; matrix.len()];
+        mapping[order[0]] = Some(node);
+        extend_complement_embedding(&matrix, &order, 1, &mut mapping, data_graph)
+    }).sum();
+    embeddings / group_size as i64
+}
+
+// Summing out a Hom pattern: vertices with at most one labelled pair left to the other
+// remaining vertices go first, each with that neighbour, until only the 2-core is left.
+// This is a tree decomposition of width one for the part of the pattern that hangs off the
+// core, a forest is summed out completely.
+pub fn hom_elimination_order(matrix: &Vec<Vec<usize>>) -> (Vec<(usize, Option<usize>)>, Vec<usize>) {
+    let mut remaining: Vec<usize> = (0..matrix.len()).collect();
+    let mut eliminated = vec![];
+    loop {
+        let labelled = |vertex: usize| -> Vec<usize> {
+            remaining.iter().copied().filter(|&other| other != vertex && matrix[vertex][other] != NO_LABEL).collect()
+        };
+        let leaf = remaining.iter().copied().map(|vertex| (vertex, labelled(vertex))).find(|(_, neighbours)| neighbours.len() <= 1);
+        let (vertex, neighbours) = match leaf {
+            Some(leaf) => leaf,
+            None => break,
+        };
+        remaining.retain(|&other| other != vertex);
+        eliminated.push((vertex, neighbours.first().copied()));
+    }
+    (eliminated, remaining)
+}
+
+// Count of a Hom term, the key being "(Hom P)": the maps that keep every label of P, where
+// an anti-edge may merge its ends and only a <> pair keeps them apart. Every pattern vertex
+// carries a weight per data vertex, the number of ways to map what was summed out into it.
+// A leaf folds its weights into its neighbour's in one pass over the adjacency, and the
+// core is matched by backtracking with every map weighted by the weights of its images.
+pub fn count_hom_term(data_graph: &DataGraph, key: &str) -> i64 {
+    let matrix = pattern_to_label_matrix(&key["(Hom ".len()..key.len() - 1]);
+    let (eliminated, core) = hom_elimination_order(&matrix);
+    let mut weights = vec![vec![1_i64; data_graph.num_nodes()]; matrix.len()];
+    let mut factor = 1;
+    for (vertex, neighbour) in eliminated {
+        let total: i64 = weights[vertex].iter().sum();
+        let neighbour = match neighbour {
+            Some(neighbour) => neighbour,
+            None => {
+                factor *= total;
+                continue;
+            },
+        };
+        let leaf_weights = &weights[vertex];
+        let message: Vec<i64> = (0..data_graph.num_nodes() as u32).into_par_iter().map(|node| {
+            let adjacent: i64 = data_graph.neighbours(node).iter().map(|&other| leaf_weights[other as usize]).sum();
+            match matrix[vertex][neighbour] {
+                EDGE_LABEL => adjacent,
+                // a data vertex is never its own neighbour, so the leaf may land on it
+                ANTI_EDGE_LABEL => total - adjacent,
+                _ => total - leaf_weights[node as usize],
+            }
+        }).collect();
+        for (weight, message) in weights[neighbour].iter_mut().zip(message) {
+            *weight *= message;
+        }
+    }
+    if core.is_empty() {
+        return factor;
+    }
+    let core_matrix = induced_label_matrix(&matrix, &core);
+    let core_weights: Vec<Vec<i64>> = core.iter().map(|&vertex| weights[vertex].clone()).collect();
+    let order = matching_order(&core_matrix, 0);
+    let homs: i64 = (0..data_graph.num_nodes() as u32).into_par_iter().map(|node| {
+        let mut mapping = vec![None; core_matrix.len()];
+        mapping[order[0]] = Some(node);
+        core_weights[order[0]][node as usize] * extend_hom(&core_matrix, &core_weights, &order, 1, &mut mapping, data_graph)
+    }).sum();
+    factor * homs
+}
+
+// Weighted backtracking for the core of a Hom pattern: like extend_embedding, but images
+// may repeat unless a <> pair or an edge keeps them apart.
+pub fn extend_hom(matrix: &Vec<Vec<usize>>, weights: &Vec<Vec<i64>>, order: &Vec<usize>, position: usize, mapping: &mut Vec<Option<u32>>, data_graph: &DataGraph) -> i64 {
+    if position == order.len() {
+        return 1;
+    }
+    let vertex = order[position];
+    let connected_to = order[..position].iter().find(|&&placed| matrix[vertex][placed] == EDGE_LABEL);
+    let candidates: Vec<u32> = match connected_to {
+        Some(&placed) => data_graph.neighbours(mapping[placed].unwrap()).to_vec(),
+        None => (0..data_graph.num_nodes() as u32).collect(),
+    };
+    let mut homs = 0;
+    'candidates: for candidate in candidates {
+        let weight = weights[vertex][candidate as usize];
+        if weight == 0 {
+            continue;
+        }
+        for &placed in order[..position].iter() {
+            let placed_node = mapping[placed].unwrap();
+            let adjacent = data_graph.has_edge(candidate, placed_node);
+            match matrix[vertex][placed] {
+                EDGE_LABEL if !adjacent => continue 'candidates,
+                ANTI_EDGE_LABEL if adjacent => continue 'candidates,
+                NOT_EQUAL_LABEL if candidate == placed_node => continue 'candidates,
+                _ => {},
+            }
+        }
+        mapping[vertex] = Some(candidate);
+        homs += weight * extend_hom(matrix, weights, order, position + 1, mapping, data_graph);
+    }
+    mapping[vertex] = None;
+    homs
+}
@@ -0,0 +1,195 @@
+use egg::*;
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::fs;
+use std::path::Path;
+
+
+// First line of the morph memo. Entries are keyed on canonical_pattern_string, so the tag
+// changes whenever the canonical form (or the line format) does, and a memo written under
+// another tag is thrown away instead of being looked up with keys that no longer match.
+const MORPH_MEMO_HEADER: &str        = "# morph memo v1, native canonical form";
+
+
+// Everything one optimization run accumulates: the estimated cost of every pattern, Const
+// formula, Complement and Hom term that was generated, the patterns still waiting for a cost
+// estimate, the (provenance, pattern) pairs that were already expanded and the Morph
+// expansions by canonical pattern. It rides along as the e-graph analysis, so appliers
+// reach it through `egraph.analysis` and two optimizers never share state.
+#[derive(Debug, Default)]
+pub struct Optimizer {
+    pub num_patterns: usize,
+    pub cost_map: HashMap<String, f64>,
+    pub patterns: Vec<String>,
+    pub formulas: Vec<String>,
+    pub complements: Vec<String>,
+    pub homs: Vec<String>,
+    pub expanded_patterns: HashSet<String>,
+    pub morph_expansions: HashMap<String, Vec<(i64, String)>>,
+    pub morph_memo_path: String,
+    pub error: Option<OptimizerError>,
+}
+
+impl Analysis<SimpleLanguage> for Optimizer {
+    type Data = ();
+
+    fn make(_egraph: &EGraph<SimpleLanguage, Self>, _enode: &SimpleLanguage) -> Self::Data {}
+
+    fn merge(&mut self, _a: &mut Self::Data, _b: Self::Data) -> DidMerge {
+        DidMerge(false, false)
+    }
+}
+
+impl Optimizer {
+    pub fn new(num_patterns: usize) -> Self {
+        Optimizer {
+            num_patterns: num_patterns,
+            morph_memo_path: DIRECTORY_PATH.to_string() + MORPH_MEMO_PATH,
+            ..Default::default()
+        }
+    }
+
+    // Registers a pattern for cost estimation, once.
+    pub fn register_pattern(&mut self, pattern: String) {
+        if !self.cost_map.contains_key(&pattern) {
+            self.patterns.push(pattern.clone());
+            self.cost_map.insert(pattern, 0.0);
+        }
+    }
+
+    // Registers a Const formula, once, along with the patterns that price it.
+    pub fn register_formula(&mut self, formula: String) {
+        if self.formulas.contains(&formula) {
+            return;
+        }
+        for pattern in get_formula_cost_patterns(&formula) {
+            self.register_pattern(pattern.to_string());
+        }
+        self.formulas.push(formula);
+    }
+
+    // Registers the complement pattern of a Complement term, priced by get_complement_cost.
+    pub fn register_complement(&mut self, pattern: String) {
+        if !self.complements.contains(&pattern) {
+            self.complements.push(pattern);
+        }
+    }
+
+    // Registers the pattern of a Hom term, priced by get_hom_cost.
+    pub fn register_hom(&mut self, pattern: String) {
+        if !self.homs.contains(&pattern) {
+            self.homs.push(pattern);
+        }
+    }
+
+    pub fn is_expanded(&self, key: &String) -> bool {
+        self.expanded_patterns.contains(key)
+    }
+
+    pub fn mark_expanded(&mut self, key: String) {
+        self.expanded_patterns.insert(key);
+    }
+
+    // Appliers can't return errors, they leave the first one here and stop rewriting;
+    // simplify hands it back once the runner stops.
+    pub fn record_error(&mut self, error: OptimizerError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    pub fn get_memoized_morph_expansion(&self, canonical_pattern: &String) -> Option<Vec<(i64, String)>> {
+        self.morph_expansions.get(canonical_pattern).cloned()
+    }
+
+    // New entries are appended to the memo right away, so an interrupted run still keeps
+    // everything it expanded.
+    pub fn memoize_morph_expansion(&mut self, canonical_pattern: String, expansion: Vec<(i64, String)>) -> Result<(), OptimizerError> {
+        let path = self.morph_memo_path.clone();
+        let mut line = canonical_pattern.clone();
+        for (coefficient, super_pattern) in expansion.iter() {
+            line += &format!("\t{}\t{}", coefficient, super_pattern);
+        }
+        let is_new = !Path::new(&path).exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)
+            .map_err(|error| OptimizerError::io(&path, error))?;
+        if is_new {
+            writeln!(file, "{}", MORPH_MEMO_HEADER).map_err(|error| OptimizerError::io(&path, error))?;
+        }
+        writeln!(file, "{}", line).map_err(|error| OptimizerError::io(&path, error))?;
+        self.morph_expansions.insert(canonical_pattern, expansion);
+        Ok(())
+    }
+
+    // MORPH_MEMO_HEADER, then one line per canonical pattern: the pattern followed by tab
+    // separated coefficient / super-pattern pairs. A missing memo just means nothing was
+    // expanded yet, and one with another header is removed and starts over.
+    pub fn load_morph_memo(&mut self) -> Result<(), OptimizerError> {
+        let path = self.morph_memo_path.clone();
+        if !Path::new(&path).exists() {
+            return Ok(());
+        }
+        let lines = read_lines(&path)?;
+        if lines.first().map(|line| line.as_str()) != Some(MORPH_MEMO_HEADER) {
+            return fs::remove_file(&path).map_err(|error| OptimizerError::io(&path, error));
+        }
+        for (index, line) in lines.iter().enumerate().skip(1) {
+            let mut fields = line.split('\t');
+            let canonical_pattern = fields.next().unwrap().to_string();
+            let fields: Vec<&str> = fields.collect();
+            let mut column = canonical_pattern.len() + 2;
+            if !fields.len().is_multiple_of(2) {
+                return Err(OptimizerError::parse(&path, index + 1, line.len() + 1, "expected coefficient / super-pattern pairs"));
+            }
+            let mut expansion = vec![];
+            for pair in fields.chunks(2) {
+                let coefficient = pair[0].parse::<i64>()
+                    .map_err(|_| OptimizerError::parse(&path, index + 1, column, &format!("unexpected '{}'", pair[0])))?;
+                expansion.push((coefficient, pair[1].to_string()));
+                column += pair[0].len() + pair[1].len() + 2;
+            }
+            self.morph_expansions.insert(canonical_pattern, expansion);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memo_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("research_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn morph_memo_round_trips() {
+        let path = memo_path("morph_memo");
+        let mut optimizer = Optimizer::new(1);
+        optimizer.morph_memo_path = path.clone();
+        let path_expansion = vec![(1, "(Match (-- a b) (-- b c) (!- a c))".to_string()), (3, "(Match (-- a b) (-- b c) (-- a c))".to_string())];
+        optimizer.memoize_morph_expansion("(Match (-- a b) (-- b c))".to_string(), path_expansion.clone()).unwrap();
+        optimizer.memoize_morph_expansion("(Match (-- a b))".to_string(), vec![]).unwrap();
+        let mut reloaded = Optimizer::new(1);
+        reloaded.morph_memo_path = path.clone();
+        reloaded.load_morph_memo().unwrap();
+        assert_eq!(reloaded.morph_expansions, optimizer.morph_expansions);
+        assert_eq!(reloaded.get_memoized_morph_expansion(&"(Match (-- a b) (-- b c))".to_string()), Some(path_expansion));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn morph_memo_with_another_header_is_discarded() {
+        let path = memo_path("stale_morph_memo");
+        fs::write(&path, "(Match (-- a b) (-- b c))\t1\t(Match (-- a b) (-- a c) (-- b c))\n").unwrap();
+        let mut optimizer = Optimizer::new(1);
+        optimizer.morph_memo_path = path.clone();
+        optimizer.load_morph_memo().unwrap();
+        assert!(optimizer.morph_expansions.is_empty());
+        assert!(!Path::new(&path).exists());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::*;
+use std::collections::HashMap;
+
+
+pub const NO_LABEL: usize        = 0;
+pub const EDGE_LABEL: usize      = 1;
+pub const ANTI_EDGE_LABEL: usize = 2;
+pub const NOT_EQUAL_LABEL: usize = 3;
+pub const MAX_LABEL: usize       = 3;
+
+pub struct AutomorphismInfo {
+    pub orbits: Vec<usize>,
+}
+
+// bliss answers with the group size on the first line and the orbit of every vertex
+// (1-based) on the second.
+pub fn find_automorphisms_with_bliss(pattern_info: &GraphInfo) -> Result<AutomorphismInfo, OptimizerError> {
+    call_bliss(pattern_info, "automorphisms")?;
+    let path = DIRECTORY_PATH.to_string() + AUTOMORPHISMS_FILE;
+    let lines = read_lines(&path)?;
+    if lines.len() < 2 {
+        return Err(OptimizerError::parse(&path, lines.len() + 1, 1, "expected the group size and the orbits"));
+    }
+    // the group size is not needed, only checked
+    parse_field::<u64>(&lines[0], 0, &path, 1)?;
+    let num_nodes = pattern_info.graph.len();
+    let mut orbits = vec![];
+    for index in 0..num_nodes {
+        let orbit = parse_field::<usize>(&lines[1], index, &path, 2)?;
+        if orbit == 0 || orbit > num_nodes {
+            return Err(OptimizerError::parse(&path, 2, 1, &format!("orbit {} outside of {} vertices", orbit, num_nodes)));
+        }
+        orbits.push(orbit - 1);
+    }
+    Ok(AutomorphismInfo {
+        orbits: orbits,
+    })
+}
+
+// A pattern as a symmetric matrix of pair labels: NO_LABEL for unconstrained pairs,
+// EDGE_LABEL for (-- u v), ANTI_EDGE_LABEL for (!- u v) and NOT_EQUAL_LABEL for (<> u v),
+// which only Hom patterns keep (see not_equal.rs).
+pub fn pattern_to_label_matrix(pattern: &str) -> Vec<Vec<usize>> {
+    let mut edges = vec![];
+    let mut anti_edges = vec![];
+    parse_pattern(&pattern.to_string(), &mut edges, &mut anti_edges);
+    let not_equal_pairs = parse_not_equal_pairs(pattern);
+    let num_nodes = get_num_nodes(pattern);
+    let mut matrix = vec![vec![NO_LABEL; num_nodes]; num_nodes];
+    for (labels, label) in [(edges, EDGE_LABEL), (anti_edges, ANTI_EDGE_LABEL), (not_equal_pairs, NOT_EQUAL_LABEL)] {
+        for (u, v) in labels {
+            let node1 = alphabet_to_number(&u) - 1;
+            let node2 = alphabet_to_number(&v) - 1;
+            matrix[node1][node2] = label;
+            matrix[node2][node1] = label;
+        }
+    }
+    matrix
+}
+
+pub fn label_matrix_to_pattern(matrix: &Vec<Vec<usize>>) -> String {
+    let mut pattern = "(Match".to_string();
+    for i in 0..matrix.len() {
+        for j in i+1..matrix.len() {
+            let atom = match matrix[i][j] {
+                EDGE_LABEL => "--",
+                ANTI_EDGE_LABEL => "!-",
+                NOT_EQUAL_LABEL => "<>",
+                _ => continue,
+            };
+            pattern += &format!(" ({} {} {})", atom, number_to_alphabet(i + 1), number_to_alphabet(j + 1));
+        }
+    }
+    pattern += ")";
+    pattern
+}
+
+fn vertex_invariant(matrix: &Vec<Vec<usize>>, vertex: usize, anchor: Option<usize>) -> (bool, Vec<usize>) {
+    let mut label_degrees = vec![0; MAX_LABEL + 1];
+    for other in 0..matrix.len() {
+        if other != vertex {
+            label_degrees[matrix[vertex][other]] += 1;
+        }
+    }
+    (anchor != Some(vertex), label_degrees)
+}
+
+fn visit_cell_permutations(cells: &Vec<Vec<usize>>, position_cells: &Vec<usize>, order: &mut Vec<usize>, used: &mut Vec<bool>, visit: &mut dyn FnMut(&Vec<usize>)) {
+    let position = order.len();
+    if position == position_cells.len() {
+        visit(order);
+        return;
+    }
+    for &vertex in cells[position_cells[position]].iter() {
+        if used[vertex] {
+            continue;
+        }
+        used[vertex] = true;
+        order.push(vertex);
+        visit_cell_permutations(cells, position_cells, order, used, visit);
+        order.pop();
+        used[vertex] = false;
+    }
+}
+
+fn permuted_upper_triangle(matrix: &Vec<Vec<usize>>, order: &Vec<usize>) -> Vec<usize> {
+    let mut labels = vec![];
+    for i in 0..order.len() {
+        for j in i+1..order.len() {
+            labels.push(matrix[order[i]][order[j]]);
+        }
+    }
+    labels
+}
+
+// Canonical form of a small labelled pattern by trying every relabelling that keeps
+// vertices inside their invariant cell. With an anchor the anchor always ends up as
+// vertex 0 ("a"). Returns the canonical matrix and the automorphism group size
+// (the number of relabellings that reach it).
+pub fn canonical_label_matrix(matrix: &Vec<Vec<usize>>, anchor: Option<usize>) -> (Vec<Vec<usize>>, u64) {
+    let num_nodes = matrix.len();
+    let mut vertices: Vec<usize> = (0..num_nodes).collect();
+    vertices.sort_by_key(|&v| vertex_invariant(matrix, v, anchor));
+    let mut cells: Vec<Vec<usize>> = vec![];
+    let mut position_cells = vec![];
+    for i in 0..num_nodes {
+        if i == 0 || vertex_invariant(matrix, vertices[i], anchor) != vertex_invariant(matrix, vertices[i - 1], anchor) {
+            cells.push(vec![]);
+        }
+        cells.last_mut().unwrap().push(vertices[i]);
+        position_cells.push(cells.len() - 1);
+    }
+    let mut best: Option<(Vec<usize>, Vec<usize>)> = None;
+    let mut group_size = 0;
+    let mut visit = |order: &Vec<usize>| {
+        let labels = permuted_upper_triangle(matrix, order);
+        let is_better = match &best {
+            None => true,
+            Some((best_labels, _)) => {
+                if labels == *best_labels {
+                    group_size += 1;
+                }
+                labels < *best_labels
+            },
+        };
+        if is_better {
+            best = Some((labels, order.clone()));
+            group_size = 1;
+        }
+    };
+    visit_cell_permutations(&cells, &position_cells, &mut vec![], &mut vec![false; num_nodes], &mut visit);
+    let (_, order) = best.unwrap();
+    let mut canonical = vec![vec![NO_LABEL; num_nodes]; num_nodes];
+    for i in 0..num_nodes {
+        for j in 0..num_nodes {
+            if i != j {
+                canonical[i][j] = matrix[order[i]][order[j]];
+            }
+        }
+    }
+    (canonical, group_size)
+}
+
+pub fn canonical_pattern_string(pattern: &str, anchor: Option<usize>) -> String {
+    let (canonical, _) = canonical_label_matrix(&pattern_to_label_matrix(pattern), anchor);
+    label_matrix_to_pattern(&canonical)
+}
+
+fn has_isolated_vertex(matrix: &Vec<Vec<usize>>) -> bool {
+    (0..matrix.len()).any(|v| !matrix[v].contains(&EDGE_LABEL))
+}
+
+// Morph expansion computed natively on the label matrix, optionally keeping an anchor fixed.
+// Patterns with unconstrained pairs are expanded into fully specified (vertex-induced)
+// super-patterns; fully specified patterns are expanded back into edge-induced ones with
+// alternating signs. Each class K gets the coefficient sign * m_K * |Aut(K)| / |Aut(P)|,
+// where m_K is the number of labelled super-patterns of P that fall into K.
+pub fn expand_label_matrix(matrix: &Vec<Vec<usize>>, anchor: Option<usize>) -> Vec<(i64, Vec<Vec<usize>>)> {
+    let mut free_pairs = vec![];
+    let mut anti_edge_pairs = vec![];
+    for i in 0..matrix.len() {
+        for j in i+1..matrix.len() {
+            if matrix[i][j] == NO_LABEL {
+                free_pairs.push((i, j));
+            }
+            else if matrix[i][j] == ANTI_EDGE_LABEL {
+                anti_edge_pairs.push((i, j));
+            }
+        }
+    }
+    let edge_induced_direction = !free_pairs.is_empty();
+    let toggled_pairs = if edge_induced_direction { free_pairs } else { anti_edge_pairs };
+    if toggled_pairs.is_empty() {
+        return vec![];
+    }
+    // dropping anti-edges must not leave a vertex without any edge, the
+    // Match syntax has no way to mention an isolated vertex
+    if !edge_induced_direction && has_isolated_vertex(matrix) {
+        return vec![];
+    }
+    let (_, pattern_group_size) = canonical_label_matrix(matrix, anchor);
+    let mut classes: HashMap<Vec<Vec<usize>>, (i64, u64)> = HashMap::new();
+    for subset in 0..(1_u64 << toggled_pairs.len()) {
+        let mut super_pattern = matrix.clone();
+        let mut sign = 1;
+        for (index, &(i, j)) in toggled_pairs.iter().enumerate() {
+            let chosen = (subset >> index) & 1 == 1;
+            let label = match (edge_induced_direction, chosen) {
+                (_, true) => EDGE_LABEL,
+                (true, false) => ANTI_EDGE_LABEL,
+                (false, false) => NO_LABEL,
+            };
+            if chosen && !edge_induced_direction {
+                sign = -sign;
+            }
+            super_pattern[i][j] = label;
+            super_pattern[j][i] = label;
+        }
+        let (canonical, group_size) = canonical_label_matrix(&super_pattern, anchor);
+        let entry = classes.entry(canonical).or_insert((0, group_size));
+        entry.0 += sign;
+    }
+    let mut expansion = vec![];
+    for (canonical, (multiplicity, group_size)) in classes {
+        let coefficient = multiplicity * group_size as i64 / pattern_group_size as i64;
+        if coefficient != 0 {
+            expansion.push((coefficient, canonical));
+        }
+    }
+    expansion.sort_by_key(|(_, canonical)| label_matrix_to_pattern(canonical));
+    expansion
+}
@@ -0,0 +1,162 @@
+use crate::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+
+// An input pattern in the Match syntax, e.g. "(Match (-- a b) (-- b c) (!- a c))",
+// together with what its count means.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pub pattern: String,
+    pub semantics: CountingSemantics,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, semantics: CountingSemantics) -> Self {
+        Pattern {
+            pattern: pattern.to_string(),
+            semantics: semantics,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    // record the rewrite chain behind every formula
+    pub explain: bool,
+    // count per data vertex, one input per vertex orbit of every pattern
+    pub local: bool,
+    // rule phases in the order they run, empty for the single all-rules phase
+    // (get_default_phases), get_staged_phases for the staged schedule
+    pub phases: Vec<Phase>,
+}
+
+// The best formula found for one input, both as extracted and as pattern (or Const
+// formula, or product of them) -> coefficient. Local inputs carry the vertex of the input
+// pattern they are anchored at.
+#[derive(Debug, Clone)]
+pub struct InputFormula {
+    pub input: usize,
+    pub anchor: Option<usize>,
+    pub expression: String,
+    pub formula: String,
+    pub terms: HashMap<String, i64>,
+    pub cost: f64,
+    pub explanation: Option<String>,
+}
+
+impl InputFormula {
+    // The count of the input, given the count of every term of the formula under its key in
+    // terms (the pattern for Morph, "(Hom P)", which count_hom_term counts, "(Local a P)",
+    // "(Join (Local a A) (Local a B))", which count_join_term counts, "(Complement P)", which
+    // count_complement_term counts, or
+    // the Const formula, which evaluate_const_formula evaluates).
+    pub fn evaluate(&self, term_value: &mut dyn FnMut(&str) -> i64) -> Result<i64, OptimizerError> {
+        evaluate_pattern_formula(&self.formula, term_value)
+    }
+}
+
+// Result of one optimization run: the set of patterns the data graph has to be matched
+// against, with the estimated cost of each, and how every input is assembled from them.
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub expression: String,
+    pub alternative_patterns: HashMap<String, i32>,
+    pub pattern_costs: HashMap<String, f64>,
+    pub cost: f64,
+    pub formulas: Vec<InputFormula>,
+    pub generation_time: Duration,
+    pub phases: Vec<PhaseStatistics>,
+}
+
+impl Plan {
+    pub fn print(&self) {
+        println!("{}", self.expression);
+        println!("generation time: {}", self.generation_time.as_secs());
+        for phase in self.phases.iter() {
+            phase.print();
+        }
+        print_alt_patterns(&self.alternative_patterns, &self.cost, &self.pattern_costs);
+        for formula in self.formulas.iter() {
+            println!();
+            match formula.anchor {
+                Some(vertex) => println!("Orbit of {} in input {}: simplified {} to {}", number_to_alphabet(vertex + 1), formula.input, formula.expression, pattern_formula_to_string(&formula.terms)),
+                None => println!("Simplified {} to {}", formula.expression, pattern_formula_to_string(&formula.terms)),
+            }
+            if let Some(explanation) = &formula.explanation {
+                println!("{}", explanation);
+            }
+        }
+    }
+
+    // Only meaningful for local plans, every formula becomes one column.
+    pub fn print_local_count_table(&self, data_graph: &DataGraph) -> Result<(), OptimizerError> {
+        print_local_count_table(&self.formulas, data_graph)
+    }
+
+    // The count of every input, with each term counted once by the native engine and every
+    // Const formula read from statistics, so the formulas share what they compute.
+    pub fn evaluate(&self, statistics: &GraphStatistics) -> Result<Vec<i64>, OptimizerError> {
+        let mut term_counts: HashMap<String, i64> = HashMap::new();
+        let mut counts = vec![];
+        for formula in self.formulas.iter() {
+            let mut error = None;
+            let count = formula.evaluate(&mut |key| {
+                if !term_counts.contains_key(key) {
+                    match count_plan_term(statistics, key) {
+                        Ok(count) => { term_counts.insert(key.to_string(), count); },
+                        Err(term_error) => {
+                            error.get_or_insert(term_error);
+                            return 0;
+                        },
+                    }
+                }
+                term_counts[key]
+            });
+            if let Some(error) = error {
+                return Err(error);
+            }
+            counts.push(count?);
+        }
+        Ok(counts)
+    }
+
+    // Evaluates the plan on data_graph and prints every count, then how long each graph
+    // statistic took.
+    pub fn print_counts(&self, data_graph: &DataGraph) -> Result<(), OptimizerError> {
+        let statistics = GraphStatistics::new(data_graph);
+        let counts = self.evaluate(&statistics)?;
+        println!();
+        for (formula, count) in self.formulas.iter().zip(counts) {
+            println!("count of input {}: {}", formula.input, count);
+        }
+        for (name, time) in statistics.timings() {
+            println!("statistic {}: {:.3}s", name, time.as_secs_f64());
+        }
+        Ok(())
+    }
+}
+
+// One term of a global formula under its evaluate_pattern_formula key. Local terms have no
+// global native count.
+pub fn count_plan_term(statistics: &GraphStatistics, key: &str) -> Result<i64, OptimizerError> {
+    let data_graph = statistics.data_graph();
+    if key.starts_with("(Match") {
+        Ok(count_pattern_term(data_graph, key))
+    }
+    else if key.starts_with("(Complement ") {
+        Ok(count_complement_term(data_graph, key))
+    }
+    else if key.starts_with("(Join ") {
+        Ok(count_join_term(data_graph, key))
+    }
+    else if key.starts_with("(Hom ") {
+        Ok(count_hom_term(data_graph, key))
+    }
+    else if key.starts_with("(Local ") {
+        Err(OptimizerError::Evaluation(format!("no native count for {}", key)))
+    }
+    else {
+        evaluate_const_formula(key, statistics)
+    }
+}
@@ -0,0 +1,165 @@
+use egg::*;
+use crate::*;
+use std::collections::HashMap;
+use std::time::Instant;
+use std::time::Duration;
+
+
+// Rules run in phases, one Runner each, on the same e-graph: a phase saturates (or runs into
+// its limits) before the next one starts, so cheap algebra can be saturated without Morph
+// expansions eating the node budget first. A phase names its rules exactly or by a prefix
+// ending in '*' ("escape-*"). The node limit applies to the whole e-graph, so it is the
+// size the e-graph may have grown to by the end of the phase, not what the phase may add.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Phase {
+    pub name: String,
+    pub rules: Vec<String>,
+    pub iter_limit: usize,
+    pub node_limit: usize,
+    pub time_limit: Duration,
+}
+
+impl Phase {
+    pub fn new(name: &str, rules: &[&str], iter_limit: usize, node_limit: usize, time_limit: Duration) -> Self {
+        Phase {
+            name: name.to_string(),
+            rules: rules.iter().map(|rule| rule.to_string()).collect(),
+            iter_limit: iter_limit,
+            node_limit: node_limit,
+            time_limit: time_limit,
+        }
+    }
+
+    fn includes(&self, rule_name: &str) -> bool {
+        self.rules.iter().any(|rule| selects_rule(rule, rule_name))
+    }
+}
+
+// A rule entry of a phase is an exact rule name or a prefix ending in '*'.
+fn selects_rule(rule: &str, rule_name: &str) -> bool {
+    match rule.strip_suffix('*') {
+        Some(prefix) => rule_name.starts_with(prefix),
+        None => rule_name == rule,
+    }
+}
+
+// What one phase did: why it stopped, how far the e-graph grew and how often each of its
+// rules applied.
+#[derive(Debug, Clone)]
+pub struct PhaseStatistics {
+    pub name: String,
+    pub iterations: usize,
+    pub stop_reason: String,
+    pub nodes: usize,
+    pub classes: usize,
+    pub applied: Vec<(String, usize)>,
+    pub time: Duration,
+}
+
+impl PhaseStatistics {
+    pub fn print(&self) {
+        println!("phase {}: {} iterations, {} nodes, {} classes, {:.3}s, stopped: {}", self.name, self.iterations, self.nodes, self.classes, self.time.as_secs_f64(), self.stop_reason);
+        for (rule, count) in self.applied.iter() {
+            println!("  {} applied {} times", rule, count);
+        }
+    }
+}
+
+// The single run the optimizer has always done: every rule together, under the limits it
+// had before there were phases.
+pub fn get_default_phases() -> Vec<Phase> {
+    vec![Phase::new("all", &["*"], 40, 100_000, Duration::from_secs(120))]
+}
+
+// Morph-like expansions first, then the escape catalogue on the patterns they produced, then
+// the count algebra to saturation. Escape rules add Morph corrections, which the last phase
+// leaves as they are. Selected with `--phases staged`.
+pub fn get_staged_phases() -> Vec<Phase> {
+    vec![
+        Phase::new("expansion", &["morph", "disconnected_morph", "cut_vertex_join", "complement_morph", "local_morph",
+            "not_equal_expansion", "sub_to_inj", "inj_to_sub", "inj_to_hom", "hom_to_inj"], 10, 60_000, Duration::from_secs(60)),
+        Phase::new("escape", &["escape-*"], 5, 80_000, Duration::from_secs(30)),
+        Phase::new("algebra", &["sum_*", "count_*", "sub_to_sum", "mul_*", "div_*"], 40, 100_000, Duration::from_secs(30)),
+    ]
+}
+
+// One phase per line: name, iteration limit, node limit, time limit in seconds and the rules,
+// e.g. "escape 5 80000 30 escape-*". Blank lines and lines starting with # are skipped, and
+// a rule name or prefix that selects none of the built-in rules is an error.
+pub fn read_phases(path: &str) -> Result<Vec<Phase>, OptimizerError> {
+    let rule_names: Vec<String> = get_static_rewrite_rules().iter().map(|rule| rule.name.to_string()).collect();
+    let mut phases = vec![];
+    for (index, line) in read_lines(path)?.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = trimmed.split_whitespace().collect();
+        if fields.len() < 5 {
+            return Err(OptimizerError::parse(path, index + 1, line.trim_end().len() + 1, "expected a name, three limits and at least one rule"));
+        }
+        let iter_limit = parse_field::<usize>(line, 1, path, index + 1)?;
+        let node_limit = parse_field::<usize>(line, 2, path, index + 1)?;
+        let time_limit = Duration::from_secs(parse_field::<u64>(line, 3, path, index + 1)?);
+        for rule in fields[4..].iter() {
+            if !rule_names.iter().any(|rule_name| selects_rule(rule, rule_name)) {
+                let column = rule.as_ptr() as usize - line.as_ptr() as usize + 1;
+                return Err(OptimizerError::parse(path, index + 1, column, &format!("no rule matches '{}'", rule)));
+            }
+        }
+        phases.push(Phase::new(fields[0], &fields[4..], iter_limit, node_limit, time_limit));
+    }
+    if phases.is_empty() {
+        return Err(OptimizerError::parse(path, 1, 1, "no phases"));
+    }
+    Ok(phases)
+}
+
+// Runs the phases in order on one e-graph, stopping at the first error an applier recorded.
+// Rules no phase names never run.
+pub fn run_phases(expr: &RecExpr<SimpleLanguage>, optimizer: Optimizer, rewrite_rules: &Vec<Rewrite<SimpleLanguage, Optimizer>>, phases: &Vec<Phase>) -> (EGraph<SimpleLanguage, Optimizer>, Id, Vec<PhaseStatistics>) {
+    // the appliers read patterns and provenances back with id_to_expr, which needs the
+    // explanation forest, so explanations are always on (and have to be before the first
+    // expression is added); --explain only decides whether they are printed
+    let mut egraph = EGraph::new(optimizer).with_explanations_enabled();
+    let root = egraph.add_expr(expr);
+    let mut statistics = vec![];
+    for phase in phases.iter() {
+        let phase_rules: Vec<&Rewrite<SimpleLanguage, Optimizer>> = rewrite_rules.iter()
+            .filter(|rule| phase.includes(rule.name.as_str()))
+            .collect();
+        let start = Instant::now();
+        let runner = Runner::default()
+            .with_egraph(egraph)
+            .with_iter_limit(phase.iter_limit)
+            .with_node_limit(phase.node_limit)
+            .with_time_limit(phase.time_limit)
+            .with_hook(|runner| match runner.egraph.analysis.error {
+                Some(_) => Err("stopped on error".to_string()),
+                None => Ok(()),
+            })
+            .run(phase_rules);
+        let mut applied: HashMap<String, usize> = HashMap::new();
+        for iteration in runner.iterations.iter() {
+            for (rule, count) in iteration.applied.iter() {
+                *applied.entry(rule.to_string()).or_insert(0) += count;
+            }
+        }
+        let mut applied: Vec<(String, usize)> = applied.into_iter().collect();
+        applied.sort();
+        statistics.push(PhaseStatistics {
+            name: phase.name.clone(),
+            iterations: runner.iterations.len(),
+            stop_reason: runner.stop_reason.as_ref().map(|reason| format!("{:?}", reason)).unwrap_or_default(),
+            nodes: runner.egraph.total_number_of_nodes(),
+            classes: runner.egraph.number_of_classes(),
+            applied: applied,
+            time: start.elapsed(),
+        });
+        egraph = runner.egraph;
+        if egraph.analysis.error.is_some() {
+            break;
+        }
+    }
+    (egraph, root, statistics)
+}
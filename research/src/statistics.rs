@@ -0,0 +1,157 @@
+use crate::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Instant;
+use std::time::Duration;
+use rayon::prelude::*;
+
+
+// The longest closed walks the store counts, enough for 5-cycles.
+pub const MAX_WALK_LENGTH: usize = 5;
+const CLOSED_WALK_NAMES: [&str; MAX_WALK_LENGTH + 1] = [
+    "closed 0-walks", "closed 1-walks", "closed 2-walks", "closed 3-walks", "closed 4-walks", "closed 5-walks",
+];
+
+
+// Graph statistics the Const formulas read, each computed on first use and then kept, so a
+// plan with several escape constants pays for every statistic once. Per-edge values are
+// indexed like the CSR neighbour array, i.e. by the position of the oriented edge (u, v).
+pub struct GraphStatistics<'a> {
+    data_graph: &'a DataGraph,
+    degrees: OnceLock<Vec<usize>>,
+    vertex_triangles: OnceLock<Vec<usize>>,
+    edge_triangles: OnceLock<Vec<usize>>,
+    wedges: OnceLock<Vec<usize>>,
+    edge_four_cycles: OnceLock<Vec<usize>>,
+    vertex_four_cycles: OnceLock<Vec<usize>>,
+    closed_walks: [OnceLock<Vec<usize>>; MAX_WALK_LENGTH + 1],
+    timings: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl<'a> GraphStatistics<'a> {
+    pub fn new(data_graph: &'a DataGraph) -> Self {
+        GraphStatistics {
+            data_graph: data_graph,
+            degrees: OnceLock::new(),
+            vertex_triangles: OnceLock::new(),
+            edge_triangles: OnceLock::new(),
+            wedges: OnceLock::new(),
+            edge_four_cycles: OnceLock::new(),
+            vertex_four_cycles: OnceLock::new(),
+            closed_walks: std::array::from_fn(|_| OnceLock::new()),
+            timings: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn data_graph(&self) -> &'a DataGraph {
+        self.data_graph
+    }
+
+    // How long every statistic computed so far took, in the order they were needed.
+    pub fn timings(&self) -> Vec<(&'static str, Duration)> {
+        self.timings.lock().unwrap().clone()
+    }
+
+    fn get_or_time<'s>(&'s self, cell: &'s OnceLock<Vec<usize>>, name: &'static str, compute: impl FnOnce() -> Vec<usize>) -> &'s [usize] {
+        cell.get_or_init(|| {
+            let start = Instant::now();
+            let values = compute();
+            self.timings.lock().unwrap().push((name, start.elapsed()));
+            values
+        })
+    }
+
+    pub fn degrees(&self) -> &[usize] {
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.degrees, "degrees", || {
+            (0..data_graph.num_nodes() as u32).map(|node| data_graph.degree(node)).collect()
+        })
+    }
+
+    pub fn vertex_triangles(&self) -> &[usize] {
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.vertex_triangles, "vertex triangles", || data_graph.vertex_triangle_counts())
+    }
+
+    pub fn edge_triangles(&self) -> &[usize] {
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.edge_triangles, "edge triangles", || {
+            get_per_edge(data_graph, |node1, node2| data_graph.common_neighbour_count(node1, node2))
+        })
+    }
+
+    // 2-paths starting at every vertex, the degrees of its neighbours minus the way back.
+    pub fn wedges(&self) -> &[usize] {
+        let degrees = self.degrees();
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.wedges, "wedges", || {
+            (0..data_graph.num_nodes() as u32).into_par_iter()
+                .map(|node| data_graph.neighbours(node).iter().map(|&neighbour| degrees[neighbour as usize] - 1).sum())
+                .collect()
+        })
+    }
+
+    // A 4-cycle through (u, v) closes a 2-path u-x with a neighbour y of v, y != u, and x-y
+    // an edge, i.e. every x in N(u) - v contributes |N(x) & N(v)| - 1 (u itself).
+    pub fn edge_four_cycles(&self) -> &[usize] {
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.edge_four_cycles, "edge 4-cycles", || {
+            get_per_edge(data_graph, |node1, node2| {
+                data_graph.neighbours(node1).iter()
+                    .filter(|&&node3| node3 != node2)
+                    .map(|&node3| data_graph.common_neighbour_count(node3, node2) - 1)
+                    .sum()
+            })
+        })
+    }
+
+    // Every 4-cycle through v passes two of its edges.
+    pub fn vertex_four_cycles(&self) -> &[usize] {
+        let edge_four_cycles = self.edge_four_cycles();
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.vertex_four_cycles, "vertex 4-cycles", || {
+            (0..data_graph.num_nodes() as u32).into_par_iter()
+                .map(|node| data_graph.edge_range(node).map(|edge| edge_four_cycles[edge]).sum::<usize>() / 2)
+                .collect()
+        })
+    }
+
+    // Closed walks of the given length at every vertex, the diagonal of A^length. Walks
+    // of length a + b from v split at their middle vertex u into A^a[v][u] * A^b[u][v], so
+    // only the sparse walk counts out of v up to half the length are kept, never A itself.
+    pub fn closed_walks(&self, length: usize) -> &[usize] {
+        let data_graph = self.data_graph;
+        self.get_or_time(&self.closed_walks[length], CLOSED_WALK_NAMES[length], || {
+            (0..data_graph.num_nodes() as u32).into_par_iter()
+                .map(|node| {
+                    let mut layers = vec![HashMap::from([(node, 1_usize)])];
+                    for _ in 0..length.div_ceil(2) {
+                        let mut next: HashMap<u32, usize> = HashMap::new();
+                        for (&vertex, &walks) in layers.last().unwrap().iter() {
+                            for &neighbour in data_graph.neighbours(vertex) {
+                                *next.entry(neighbour).or_insert(0) += walks;
+                            }
+                        }
+                        layers.push(next);
+                    }
+                    let (longer, shorter) = (&layers[length.div_ceil(2)], &layers[length / 2]);
+                    longer.iter().map(|(vertex, &walks)| walks * shorter.get(vertex).copied().unwrap_or(0)).sum::<usize>()
+                })
+                .collect()
+        })
+    }
+
+    // The value of a per-edge statistic for the edge (node1, node2), None if there is no such edge.
+    pub fn edge_value(&self, values: &[usize], node1: u32, node2: u32) -> Option<usize> {
+        let position = self.data_graph.neighbours(node1).binary_search(&node2).ok()?;
+        Some(values[self.data_graph.edge_range(node1).start + position])
+    }
+}
+
+fn get_per_edge(data_graph: &DataGraph, value: impl Fn(u32, u32) -> usize + Sync) -> Vec<usize> {
+    let per_node: Vec<Vec<usize>> = (0..data_graph.num_nodes() as u32).into_par_iter()
+        .map(|node1| data_graph.neighbours(node1).iter().map(|&node2| value(node1, node2)).collect())
+        .collect();
+    per_node.concat()
+}
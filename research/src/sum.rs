@@ -0,0 +1,297 @@
+use egg::*;
+use crate::*;
+use std::collections::BTreeMap;
+
+
+// A linear combination of counts is one n-ary node
+//   (Sum c1 t1 c2 t2 ...)
+// with Num coefficients and the terms sorted by e-class, so every arrangement of the same
+// terms is the same e-node and commutativity and associativity never have to be rewritten.
+// sum_flatten inlines the Sum and Count terms of a Sum, sum_dedup merges the Morph and
+// Const terms that count the same pattern or formula for different inputs and re-sorts the
+// Sums a rebuild left out of order, and count_dist pushes a Count coefficient into a Sum.
+
+// Builds the canonical Sum of the given terms: repeated terms are added up, zero
+// coefficients are dropped and the rest is sorted by e-class. When everything cancels the
+// first term is kept with coefficient 0. None when a coefficient overflows. Only the new
+// node is canonical: a rebuild that merges term e-classes can leave a Sum unsorted or with
+// a repeated term, and sum_dedup normalizes it again.
+pub fn add_sum(egraph: &mut EGraph<SimpleLanguage, Optimizer>, terms: Vec<(i64, Id)>) -> Option<Id> {
+    let mut coefficients: BTreeMap<Id, i64> = BTreeMap::new();
+    for (coefficient, term) in terms {
+        let sum = coefficients.entry(egraph.find(term)).or_insert(0);
+        *sum = sum.checked_add(coefficient)?;
+    }
+    let first_term = *coefficients.keys().next()?;
+    coefficients.retain(|_, coefficient| *coefficient != 0);
+    if coefficients.is_empty() {
+        coefficients.insert(first_term, 0);
+    }
+    let mut children = vec![];
+    for (term, coefficient) in coefficients {
+        children.push(egraph.add(SimpleLanguage::Num(coefficient)));
+        children.push(term);
+    }
+    Some(egraph.add(SimpleLanguage::Sum(children.into_boxed_slice())))
+}
+
+// What add_sum builds: terms in increasing e-class order, none repeated, and no zero
+// coefficient unless it is the only term.
+fn is_canonical_sum(terms: &Vec<(i64, Id)>) -> bool {
+    terms.windows(2).all(|pair| pair[0].1 < pair[1].1) && (terms.len() == 1 || terms.iter().all(|&(coefficient, _)| coefficient != 0))
+}
+
+// The (coefficient, term) pairs of every Sum node in an e-class.
+pub fn get_sum_nodes(egraph: &EGraph<SimpleLanguage, Optimizer>, eclass: Id) -> Vec<Vec<(i64, Id)>> {
+    let mut sums = vec![];
+    for node in egraph[eclass].nodes.iter() {
+        if let SimpleLanguage::Sum(children) = node {
+            sums.push(children.chunks(2).map(|pair| (get_num(egraph, pair[0]), egraph.find(pair[1]))).collect());
+        }
+    }
+    sums
+}
+
+// Every way an e-class can be written as a combination of other terms: its Sum nodes and
+// its Count nodes as single terms.
+fn get_linear_forms(egraph: &EGraph<SimpleLanguage, Optimizer>, eclass: Id) -> Vec<Vec<(i64, Id)>> {
+    let mut forms = get_sum_nodes(egraph, eclass);
+    for node in egraph[eclass].nodes.iter() {
+        if let SimpleLanguage::Count([num, rest]) = node {
+            forms.push(vec![(get_num(egraph, *num), egraph.find(*rest))]);
+        }
+    }
+    forms
+}
+
+pub fn get_num(egraph: &EGraph<SimpleLanguage, Optimizer>, id: Id) -> i64 {
+    find_num(egraph, id).unwrap()
+}
+
+pub fn find_num(egraph: &EGraph<SimpleLanguage, Optimizer>, id: Id) -> Option<i64> {
+    egraph[id].nodes.iter().find_map(|node| match node {
+        SimpleLanguage::Num(num) => Some(*num),
+        _ => None,
+    })
+}
+
+fn scale_terms(terms: &Vec<(i64, Id)>, num: i64) -> Option<Vec<(i64, Id)>> {
+    terms.iter().map(|&(coefficient, term)| Some((coefficient.checked_mul(num)?, term))).collect()
+}
+
+// Finds the e-classes holding a Sum. A Pattern only matches one arity, so sum_flatten and
+// sum_dedup search with this instead.
+pub struct SumSearcher {
+    pub sum: Var,
+}
+
+impl Searcher<SimpleLanguage, Optimizer> for SumSearcher {
+    fn search_eclass_with_limit(&self, egraph: &EGraph<SimpleLanguage, Optimizer>, eclass: Id, limit: usize) -> Option<SearchMatches<'_, SimpleLanguage>> {
+        let has_sum = egraph[eclass].nodes.iter().any(|node| matches!(node, SimpleLanguage::Sum(_)));
+        if limit == 0 || !has_sum {
+            return None;
+        }
+        let mut subst = Subst::with_capacity(1);
+        subst.insert(self.sum, eclass);
+        Some(SearchMatches {
+            eclass: eclass,
+            substs: vec![subst],
+            ast: None,
+        })
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        vec![self.sum]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SumFlatten {
+    pub sum: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for SumFlatten {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let sum_id = egraph.find(subst[self.sum]);
+        let mut new_ids = vec![];
+        for terms in get_sum_nodes(egraph, sum_id) {
+            for index in 0..terms.len() {
+                let (coefficient, term) = terms[index];
+                if term == sum_id {
+                    continue;
+                }
+                // one term at a time, the other terms keep every choice of their own e-class
+                for form in get_linear_forms(egraph, term) {
+                    let mut new_terms = terms.clone();
+                    new_terms.remove(index);
+                    match scale_terms(&form, coefficient) {
+                        Some(inner_terms) => new_terms.extend(inner_terms),
+                        None => continue,
+                    }
+                    let new_id = match add_sum(egraph, new_terms) {
+                        Some(new_id) => new_id,
+                        None => continue,
+                    };
+                    if union_with_reason(egraph, matched_id, new_id, rule_name) {
+                        new_ids.push(new_id);
+                    }
+                }
+            }
+        }
+        new_ids
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SumDedup {
+    pub sum: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for SumDedup {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let sum_id = egraph.find(subst[self.sum]);
+        let mut new_ids = vec![];
+        for terms in get_sum_nodes(egraph, sum_id) {
+            let new_id = match dedup_sum_terms(egraph, &terms) {
+                Ok(Some(new_id)) => new_id,
+                Ok(None) => continue,
+                Err(error) => {
+                    egraph.analysis.record_error(error);
+                    return vec![];
+                },
+            };
+            if union_with_reason(egraph, matched_id, new_id, rule_name) {
+                new_ids.push(new_id);
+            }
+        }
+        new_ids
+    }
+}
+
+// Merges c1 (Morph pi1 P) + c2 (Morph pi2 P) into (c1 + c2) (Morph pi1+pi2 P), and the same
+// for Const formulas, so a pattern shared between inputs is only matched once. Terms that
+// cancel are dropped, and a Sum where everything cancels becomes (Const pi 0). Without
+// anything to merge, a Sum that is no longer canonical is rebuilt with add_sum. None when
+// there was nothing to do.
+fn dedup_sum_terms(egraph: &mut EGraph<SimpleLanguage, Optimizer>, terms: &Vec<(i64, Id)>) -> Result<Option<Id>, OptimizerError> {
+    let mut groups: BTreeMap<(bool, Id), Vec<(i64, Id, Id)>> = BTreeMap::new();
+    let mut new_terms = vec![];
+    for &(coefficient, term) in terms.iter() {
+        let counted = egraph[term].nodes.iter().find_map(|node| match node {
+            SimpleLanguage::Morph([provenance, pattern]) => Some((false, *provenance, *pattern)),
+            SimpleLanguage::Const([provenance, formula]) => Some((true, *provenance, *formula)),
+            _ => None,
+        });
+        match counted {
+            Some((is_const, provenance, counted)) => {
+                groups.entry((is_const, egraph.find(counted))).or_insert(vec![]).push((coefficient, egraph.find(provenance), term));
+            },
+            None => new_terms.push((coefficient, term)),
+        }
+    }
+    if groups.values().all(|group| group.len() == 1) {
+        return Ok(if is_canonical_sum(terms) { None } else { add_sum(egraph, terms.clone()) });
+    }
+    let mut cancelled_provenance = None;
+    for ((is_const, counted), group) in groups {
+        if group.len() == 1 {
+            new_terms.push((group[0].0, group[0].2));
+            continue;
+        }
+        let mut coefficient: i64 = 0;
+        let mut provenance_string = format!("{}", egraph.id_to_expr(group[0].1));
+        for &(other_coefficient, other_provenance, _) in group.iter() {
+            coefficient = match coefficient.checked_add(other_coefficient) {
+                Some(coefficient) => coefficient,
+                None => return Ok(None),
+            };
+            let other_provenance_string = format!("{}", egraph.id_to_expr(other_provenance));
+            if other_provenance_string != provenance_string {
+                provenance_string = "(Pi ".to_string() + &get_merged_provenance(&provenance_string, &other_provenance_string)? + ")";
+            }
+        }
+        let provenance_recexpr: RecExpr<SimpleLanguage> = provenance_string.parse().unwrap();
+        let provenance_id = egraph.add_expr(&provenance_recexpr);
+        if coefficient == 0 {
+            cancelled_provenance = Some(provenance_id);
+            continue;
+        }
+        let new_term = if is_const {
+            egraph.add(SimpleLanguage::Const([provenance_id, counted]))
+        }
+        else {
+            egraph.add(SimpleLanguage::Morph([provenance_id, counted]))
+        };
+        new_terms.push((coefficient, new_term));
+    }
+    if new_terms.is_empty() {
+        let formula_id = egraph.add(SimpleLanguage::Num(0));
+        let const_id = egraph.add(SimpleLanguage::Const([cancelled_provenance.unwrap(), formula_id]));
+        new_terms.push((1, const_id));
+    }
+    Ok(add_sum(egraph, new_terms))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountDist {
+    pub num: Var,
+    pub terms: Var,
+}
+
+impl Applier<SimpleLanguage, Optimizer> for CountDist {
+    fn apply_one(&self, egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, subst: &Subst, _searcher_pattern: Option<&PatternAst<SimpleLanguage>>, rule_name: Symbol) -> Vec<Id> {
+        let num = get_num(egraph, subst[self.num]);
+        let mut new_ids = vec![];
+        for terms in get_sum_nodes(egraph, subst[self.terms]) {
+            let new_id = match scale_terms(&terms, num).and_then(|new_terms| add_sum(egraph, new_terms)) {
+                Some(new_id) => new_id,
+                None => continue,
+            };
+            if union_with_reason(egraph, matched_id, new_id, rule_name) {
+                new_ids.push(new_id);
+            }
+        }
+        new_ids
+    }
+}
+
+// The (coefficient, term) pairs of a Sum, from the rest of "(Sum c1 t1 c2 t2 ...)".
+pub fn parse_sum_string(input: String) -> Result<Vec<(i64, String)>, OptimizerError> {
+    let items = parse_operands_string(input);
+    items.chunks(2).map(|pair| Ok((parse_coefficient(&pair[0])?, pair[1].clone()))).collect()
+}
+
+// A Sum or Count coefficient, which is a Num and so spans the whole i64 range.
+pub fn parse_coefficient(input: &str) -> Result<i64, OptimizerError> {
+    input.parse().map_err(|_| OptimizerError::Evaluation(format!("unexpected coefficient '{}' in a formula", input)))
+}
+
+// The top level atoms and s-expressions in the rest of an s-expression, e.g. the two
+// operands of "(Mul (Morph ...) (Morph ...))".
+pub fn parse_operands_string(input: String) -> Vec<String> {
+    let inner = &input[..input.len() - 1];
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut start = None;
+    for (i, c) in inner.char_indices() {
+        if depth == 0 && start.is_none() && !c.is_whitespace() {
+            start = Some(i);
+        }
+        if c == '(' {
+            depth += 1;
+        }
+        if c == ')' {
+            depth -= 1;
+        }
+        if depth == 0 && (c == ')' || c.is_whitespace()) {
+            if let Some(item_start) = start.take() {
+                let end = if c == ')' { i + 1 } else { i };
+                items.push(inner[item_start..end].trim().to_string());
+            }
+        }
+    }
+    if let Some(item_start) = start {
+        items.push(inner[item_start..].trim().to_string());
+    }
+    items
+}
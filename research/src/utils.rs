@@ -1,114 +1,46 @@
 use egg::*;
-use libc::{rewind, CIBAUD, EPOLLRDHUP, GENL_UNS_ADMIN_PERM};
-use core::num;
-use std::collections::btree_map::Range;
-use std::collections::{HashMap, HashSet, *};
-use std::f64::consts;
-use std::fmt::format;
-use std::hash::Hash;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
 use std::ffi::CString;
 use std::fs::{File, DirEntry};
-use std::io::prelude::*;
 use std::fs::OpenOptions;
-use std::ops::Sub;
-use std::{env, fs, i64, process, result, usize};
-use std::cmp;
-use bimap::{BiMap, BiHashMap};
+use std::io::Write;
+use std::{fs, process};
 use factorial::Factorial;
-use std::collections::VecDeque;
-use regex::{Match, Regex};
-use lazy_static::lazy_static;
-use std::sync::Mutex;
+use regex::Regex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
-use std::cell::UnsafeCell;
 use std::process::Command;
-use std::str;
-use std::time::Duration;
-
-pub const DIRECTORY_PATH: &'static str       = "/tmp/gql/";
-pub const SRC_GRAPH_BLISS_FILE: &'static str = "src_graph.txt";
-pub const BLISS_PIPE_NAME: &'static str      = "my_pipe";
-pub const MORPH_PIPE_NAME: &'static str      = "morph_pipe";
-pub const RESULT_DIRECTORY: &'static str     = "result";
-pub const COST_DIRECTORY: &'static str       = "cost/";
-pub const SRC_DIRECTORY: &'static str        = "src/";
-pub const USER_RULES_PATH: &'static str      = "user_rules/";
-pub const DATA_GRAPH_PATH: &'static str      = "mico.lg";
-pub const PEREGRINE_DIRECTORY: &'static str  = "peregrine/";
-
-static NUM_PATTERNS: Mutex<usize> = Mutex::new(0);
-
-fn simplify(s: &str, dynamic_rewrite_rules: Vec<Rewrite<SimpleLanguage, ()>>) -> (String, f64, EGraph<SimpleLanguage, ()>) {
-    let expr: RecExpr<SimpleLanguage> = s.parse().unwrap();
+use rayon::prelude::*;
+
+pub const PEREGRINE_COUNT_PATH: &str = "../peregrine-master/bin/count";
+pub const PEREGRINE_CONVERT_PATH: &str = "../peregrine-master/bin/convert_data";
+
+
+// Saturates in the given phases (the default schedule when there are none, see schedule.rs),
+// prices every pattern that came up and extracts the cheapest plan.
+pub fn simplify(s: &str, optimizer: Optimizer, data_graph: &DataGraph, dynamic_rewrite_rules: Vec<Rewrite<SimpleLanguage, Optimizer>>, phases: &Vec<Phase>) -> Result<(String, f64, EGraph<SimpleLanguage, Optimizer>, Vec<PhaseStatistics>), OptimizerError> {
+    let expr: RecExpr<SimpleLanguage> = s.parse().map_err(|error| OptimizerError::parse(s, 1, 1, &format!("{}", error)))?;
     let mut rewrite_rules = get_static_rewrite_rules();
     rewrite_rules.extend(dynamic_rewrite_rules);
     let start = Instant::now();
-    //default
-    // let mut runner = Runner::default().with_iter_limit(40).with_node_limit(50_000).with_time_limit(Duration::from_secs(240)).with_expr(&expr).run(&rewrite_rules);
-    let mut runner = Runner::default().with_iter_limit(40).with_node_limit(100_000).with_time_limit(Duration::from_secs(120)).with_expr(&expr).run(&rewrite_rules);
-    find_patterns_costs();
-    // custom_costs();
-    let root = runner.roots[0];
+    let phases = if phases.is_empty() { get_default_phases() } else { phases.clone() };
+    // stop as soon as an applier ran into an error instead of rewriting on without bliss or peregrine
+    let (mut egraph, root, phase_statistics) = run_phases(&expr, optimizer, &rewrite_rules, &phases);
+    if let Some(error) = egraph.analysis.error.take() {
+        return Err(error);
+    }
+    find_patterns_costs(&mut egraph.analysis, data_graph)?;
+    // custom_costs(&mut egraph.analysis.cost_map);
     let end = Instant::now();
-    let current_run_time = (end - start).as_secs();
-    let extractor= Extractor::new(&runner.egraph, NaiveCostFunction);
-    let (best_cost, best) = extractor.find_best(root);
+    let _current_run_time = (end - start).as_secs();
+    let (best_cost, best) = Extractor::new(&egraph, NaiveCostFunction { egraph: &egraph }).find_best(root);
     println!();
     // println!("best is {}", best);
-    (best.to_string(), best_cost, runner.egraph)
-}
-
-// fn simplify(s: &str, dynamic_rewrite_rules: Vec<Rewrite<SimpleLanguage, ()>>) -> (String, f64, EGraph<SimpleLanguage, ()>) {
-//     let expr: RecExpr<SimpleLanguage> = s.parse().unwrap();
-//     let mut rewrite_rules = get_static_rewrite_rules();
-//     rewrite_rules.extend(dynamic_rewrite_rules);
-
-//     let start = Instant::now();
-//     let mut runner = Runner::default()
-//         .with_explanations_enabled()
-//         .with_expr(&expr)
-//         .run(&rewrite_rules);
-
-//     let end = Instant::now();
-//     let current_run_time = (end - start).as_secs();
-//     find_patterns_costs();
-//     let root = runner.roots[0];
-
-//     // Clone the egraph to use in the separate thread
-//     let egraph_clone = runner.egraph.clone();
-
-//     // Timeout setup
-//     let (tx, rx) = mpsc::channel();
-//     let timeout = Duration::from_secs(1 - current_run_time);
-
-//     // Run extractor in a separate thread with the cloned egraph
-//     thread::spawn(move || {
-//         let extractor = Extractor::new(&egraph_clone, NaiveCostFunction);
-//         let result = extractor.find_best(root);
-//         let _ = tx.send(result); // Send result back to main thread
-//     });
-
-//     // Initialize best_cost and best with default values
-//     let mut best_cost = f64::INFINITY; // Assign default cost for timeout
-//     let mut best = expr.clone(); // Default to the input expression if extraction times out
-
-//     // Wait for the result with a timeout
-//     match rx.recv_timeout(timeout) {
-//         Ok(result) => {
-//             best_cost = result.0;
-//             best = result.1;
-//         },
-//         Err(_) => {
-//             println!("Extractor timed out after 10 minutes.");
-//         }
-//     };
-
-
-//     println!();
-//     println!("best is {}", best);
-//     (best.to_string(), best_cost, runner.egraph) // `runner.egraph` is still accessible here
-// }
+    Ok((best.to_string(), best_cost, egraph, phase_statistics))
+}
+
 
 fn make_graph_file(graph_info: &GraphInfo, bliss_format: bool) -> Vec<String> {
     let mut bliss_graph = vec![];
@@ -133,40 +65,77 @@ fn make_graph_file(graph_info: &GraphInfo, bliss_format: bool) -> Vec<String> {
     lines
 }
 
-fn create_pipe(pipe_name: &str) {
+// Custom appliers union by hand, so egg only knows the call site as the reason.
+// Passing the rule name keeps explanations readable (morph, escape-N3, count_mult, ...).
+pub fn union_with_reason(egraph: &mut EGraph<SimpleLanguage, Optimizer>, matched_id: Id, new_id: Id, rule_name: Symbol) -> bool {
+    if egraph.are_explanations_enabled() {
+        egraph.union_trusted(matched_id, new_id, rule_name)
+    }
+    else {
+        egraph.union(matched_id, new_id)
+    }
+}
+
+fn collect_rewrite_rules(term: &FlatTerm<SimpleLanguage>, rules: &mut Vec<String>) {
+    if let Some(rule) = term.forward_rule {
+        rules.push(rule.to_string());
+    }
+    if let Some(rule) = term.backward_rule {
+        rules.push(format!("{} (reversed)", rule));
+    }
+    for child in term.children.iter() {
+        collect_rewrite_rules(child, rules);
+    }
+}
+
+pub fn get_rewrite_explanation(input: &RecExpr<SimpleLanguage>, best: &RecExpr<SimpleLanguage>, egraph: &mut EGraph<SimpleLanguage, Optimizer>) -> String {
+    let mut explanation = egraph.explain_equivalence(input, best);
+    let mut rules = vec![];
+    for term in explanation.make_flat_explanation().iter() {
+        collect_rewrite_rules(term, &mut rules);
+    }
+    format!("Rewrite chain ({} steps): {}\n{}", rules.len(), rules.join(" -> "), explanation.get_flat_string())
+}
+
+fn create_pipe(pipe_name: &str) -> Result<(), OptimizerError> {
     let path = DIRECTORY_PATH.to_string() + pipe_name;
-    let filename = CString::new(path).unwrap();
-    unsafe {
-        libc::mkfifo(filename.as_ptr(), 0o777);
+    let filename = CString::new(path.clone()).unwrap();
+    let result = unsafe {
+        libc::mkfifo(filename.as_ptr(), 0o777)
+    };
+    let error = std::io::Error::last_os_error();
+    // the pipes outlive a run, bliss and the cost estimator keep them open
+    if result != 0 && error.kind() != std::io::ErrorKind::AlreadyExists {
+        return Err(OptimizerError::io(&path, error));
     }
+    Ok(())
 }
 
-fn write_to_pipe(pipe_name: &str, message: String) {
+pub fn write_to_pipe(pipe_name: &str, message: String) -> Result<(), OptimizerError> {
     let path = DIRECTORY_PATH.to_string() + pipe_name;
     let mut file: File = OpenOptions::new()
-        .write(true)
         .append(true)
-        .open(path)
-        .unwrap();
-
-    file.write_all(message.as_bytes()).expect("failed");
+        .open(&path)
+        .map_err(|error| OptimizerError::io(&path, error))?;
 
+    file.write_all(message.as_bytes()).map_err(|error| OptimizerError::io(&path, error))
 }
 
-fn write_to_file(lines: Vec<String>, path: String) {
-    let mut file = File::create(path).expect("error");
+pub fn write_to_file(lines: Vec<String>, path: String) -> Result<(), OptimizerError> {
+    let mut file = File::create(&path).map_err(|error| OptimizerError::io(&path, error))?;
     let content = lines.join("\n");
-    file.write_all(content.as_bytes()).expect("failed");
+    file.write_all(content.as_bytes()).map_err(|error| OptimizerError::io(&path, error))
 }
 
-fn get_done_signal() {
+fn get_done_signal() -> Result<(), OptimizerError> {
     let pipe_path = DIRECTORY_PATH.to_string() + BLISS_PIPE_NAME;
-    let file = File::open(pipe_path).unwrap();
+    let file = File::open(&pipe_path).map_err(|error| OptimizerError::io(&pipe_path, error))?;
     let reader = BufReader::new(file);
     reader.lines().next();
+    Ok(())
 }
 
-fn number_to_alphabet(mut num: usize) -> String {
+pub fn number_to_alphabet(mut num: usize) -> String {
     let mut result = String::new();
     while num > 0 {
         num -= 1;
@@ -176,7 +145,7 @@ fn number_to_alphabet(mut num: usize) -> String {
     result.chars().rev().collect()
 }
 
-fn alphabet_to_number(s: &str) -> usize {
+pub fn alphabet_to_number(s: &str) -> usize {
     s.chars().fold(0, |acc, c| acc * 26 + (c as usize - 'a' as usize + 1))
 }
 
@@ -194,7 +163,7 @@ fn get_edges_in_string_format(input: &str) -> Vec<(String, String)> {
     let edge_tuples: Vec<(String, String)> = edges
         .iter()
         .map(|&edge| {
-            let components: Vec<&str> = edge.trim().split_whitespace().collect();
+            let components: Vec<&str> = edge.split_whitespace().collect();
             if components.len() == 2 {
                 (components[0].to_string(), components[1].to_string())
             } else {
@@ -216,162 +185,122 @@ fn build_graph_adjacency_matrix(edges_string: &Vec<(String, String)>, num_nodes:
         input_graph_matrix[node2][node1] = 1;
         num_edges += 1;
     }
-    let graph_info = GraphInfo {
+    GraphInfo {
         graph: input_graph_matrix,
         num_edges: num_edges,
-    };
-    graph_info
+    }
 }
 
-struct GraphInfo {
-    graph: Vec<Vec<usize>>,
-    num_edges: i32,
+pub struct GraphInfo {
+    pub graph: Vec<Vec<usize>>,
+    pub num_edges: i32,
 }
 
-fn parse_input(input: &str, num_nodes: usize) -> GraphInfo {
-    let edges_string = get_edges_in_string_format(&input);
+pub fn parse_input(input: &str, num_nodes: usize) -> GraphInfo {
+    let edges_string = get_edges_in_string_format(input);
     let input_graph = build_graph_adjacency_matrix(&edges_string, num_nodes);
-    let input_graph_info = GraphInfo {
+    GraphInfo {
         graph : input_graph.graph,
         num_edges: input_graph.num_edges,
-    };
-    input_graph_info
+    }
 }
 
-// fn get_num_nodes(edges: &Vec<(String, String)>) -> usize {
-//     let mut nodes = HashSet::new();
-//     for edge in edges.iter() {
-//         nodes.insert(edge.0.clone());
-//         nodes.insert(edge.1.clone());
-//     }
-//     nodes.len()
-// }
 
-fn get_num_nodes(input: &str) -> usize {
+pub fn get_num_nodes(input: &str) -> usize {
     let cleaned = input.replace("(", "").replace(")", "");
     let components: Vec<&str> = cleaned.split_whitespace().collect();
     let mut nodes = HashSet::new();
     for component in components {
-        if component.to_string() != "Match" && component.to_string() != "--" && component.to_string() != "!-" {
+        if component != "Match" && component != "--" && component != "!-" && component != "<>" {
             nodes.insert(component);
         }
     }
     nodes.len()
 }
 
-fn clique(nodes: HashSet<String>) -> HashSet<(String, String)> {
-    let mut all_edges = HashSet::new();
-    for v1 in nodes.iter() {
-        for v2 in nodes.iter() {
-            if v1 == v2 {
-                continue;
-            }
-            let edge = (v1.clone(), v2.clone());
-            if !all_edges.contains(&edge) && !all_edges.contains(&(edge.1.clone(), edge.0.clone())) {
-                all_edges.insert(edge);
-            }
-        }
-
-    }
-    all_edges
-}
-
-fn create_file_reader(entry: &DirEntry) -> BufReader<File> {
+fn create_file_reader(entry: &DirEntry) -> Result<BufReader<File>, OptimizerError> {
     let result_directory = DIRECTORY_PATH.to_string() + RESULT_DIRECTORY;
     let file_name = entry.file_name().to_string_lossy().into_owned();
-    let file = File::open(result_directory.to_owned() + "/" + &file_name).unwrap();
+    let path = result_directory.to_owned() + "/" + &file_name;
+    let file = File::open(&path).map_err(|error| OptimizerError::io(&path, error))?;
     let reader = BufReader::new(file);
-    reader
-}
-
-fn parse_graph_edges(reader: BufReader<File>) -> Vec<(i32, i32)> {
-    let mut edges = Vec::new();
-    for line_result in reader.lines() {
-        let line = line_result.unwrap();
-        if line.starts_with("e ") {
-            let mut parts = line.split_whitespace();
-            parts.next();
-            if let (Some(first_num_str), Some(second_num_str)) = (parts.next(), parts.next()) {
-                if let (Ok(first_num), Ok(second_num)) = (first_num_str.parse::<i32>(), second_num_str.parse::<i32>()) {
-                    edges.push((first_num, second_num));
-                }
-            }
-        }
-     }
-    edges
+    Ok(reader)
 }
 
-fn get_number_of_nodes_from_bliss_file(first_line: String) -> usize {
-    let mut parts = first_line.split_whitespace();
-    let p = parts.next().unwrap();
-    let e = parts.next().unwrap();
-    let nodes_num = parts.next().unwrap().parse::<usize>().unwrap();
-    nodes_num
+// The "p edge <nodes> <edges>" header of a bliss (DIMACS) file.
+fn get_number_of_nodes_from_bliss_file(first_line: String, path: &str) -> Result<usize, OptimizerError> {
+    parse_field::<usize>(&first_line, 2, path, 1)
 }
 
-fn read_graph_from_bliss_file(reader: &mut BufReader<File>, coefficient: &mut i32) -> GraphInfo {
-    let nodes_num = get_number_of_nodes_from_bliss_file(reader.lines().next().unwrap().unwrap());
+fn read_graph_from_bliss_file(reader: &mut BufReader<File>, path: &str, coefficient: &mut i32) -> Result<GraphInfo, OptimizerError> {
+    let mut lines = reader.lines();
+    let first_line = match lines.next() {
+        Some(line_result) => line_result.map_err(|error| OptimizerError::io(path, error))?,
+        None => return Err(OptimizerError::parse(path, 1, 1, "empty bliss file")),
+    };
+    let nodes_num = get_number_of_nodes_from_bliss_file(first_line, path)?;
     let mut num_edges = 0;
     let mut graph = vec![vec![0; nodes_num]; nodes_num];
-    for line_result in reader.lines() {
-        let line = line_result.unwrap();
+    for (index, line_result) in lines.enumerate() {
+        let line = line_result.map_err(|error| OptimizerError::io(path, error))?;
+        let line_number = index + 2;
         if line.starts_with("n ") {
             continue;
         }
         else if line.starts_with("e ") {
-            let mut parts = line.split_whitespace();
-            parts.next();
-            let u = parts.next().unwrap().parse::<usize>().unwrap() - 1;
-            let v = parts.next().unwrap().parse::<usize>().unwrap() - 1;
-            graph[u][v] = 1;
-            graph[v][u] = 1;
+            let u = parse_field::<usize>(&line, 1, path, line_number)?;
+            let v = parse_field::<usize>(&line, 2, path, line_number)?;
+            if u == 0 || v == 0 || u > nodes_num || v > nodes_num {
+                return Err(OptimizerError::parse(path, line_number, 1, &format!("edge {} {} outside of {} vertices", u, v, nodes_num)));
+            }
+            graph[u - 1][v - 1] = 1;
+            graph[v - 1][u - 1] = 1;
             num_edges += 1;
         }
         else {
-            *coefficient = line.parse::<i32>().unwrap();
+            *coefficient = parse_field::<i32>(&line, 0, path, line_number)?;
         }
      }
      let graph_info = GraphInfo {
         graph: graph,
         num_edges: num_edges,
      };
-    graph_info
+    Ok(graph_info)
 }
 
-fn is_graph_complete(num_edges: i32, num_nodes: i32) -> bool {
+pub fn is_graph_complete(num_edges: i32, num_nodes: i32) -> bool {
     num_edges == (calculate_permutation(num_nodes as u64, 2) as i32)
 }
 
-fn generate_morph_rule_using_subtraction(pattern: &str, edge_induced_pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>) -> Id {
-    let anti_edges_set = collect_anti_edges_numbers(pattern);
-    let is_edge_induced_pattern = pattern == edge_induced_pattern;
-    let num_nodes = get_num_nodes(pattern);
-    let pattern_info: GraphInfo = parse_input(&edge_induced_pattern, num_nodes);
-    call_bliss(&pattern_info, "vertex_induced");
+// Super-patterns bliss wrote to RESULT_DIRECTORY, one file each. The files are removed once
+// read so the next bliss call starts from an empty directory.
+fn read_bliss_result_graphs() -> Result<Vec<GraphInfo>, OptimizerError> {
     let result_directory = DIRECTORY_PATH.to_string() + RESULT_DIRECTORY;
-    let peregrine_directory = DIRECTORY_PATH.to_string() + PEREGRINE_DIRECTORY;
-    fs::create_dir(&peregrine_directory);
-    let entries = fs::read_dir(result_directory).unwrap();
-    let mut index = 0;
-    let mut union_id = Id::from(0_usize); 
-    let mut index = 0;
+    let entries = fs::read_dir(&result_directory).map_err(|error| OptimizerError::io(&result_directory, error))?;
     let mut graph_infos = vec![];
     for entry in entries {
-        index += 1;
-        let dir_entry = entry.unwrap();
-        let mut reader = create_file_reader(&dir_entry);
+        let dir_entry = entry.map_err(|error| OptimizerError::io(&result_directory, error))?;
+        let path = dir_entry.path().to_string_lossy().into_owned();
+        let mut reader = create_file_reader(&dir_entry)?;
         let mut coefficient = 0;
-        let new_graph_info  = read_graph_from_bliss_file(&mut reader, &mut coefficient); 
-        make_peregrine_datagraph(&new_graph_info.graph, index);
+        let new_graph_info  = read_graph_from_bliss_file(&mut reader, &path, &mut coefficient)?;
         graph_infos.push(new_graph_info);
-        fs::remove_file(&dir_entry.path());
+        fs::remove_file(&path).map_err(|error| OptimizerError::io(&path, error))?;
     }
-    write_pattern_to_peregrine(&pattern_info.graph, &anti_edges_set, false);
-    let mut index = 1;
-    let mut first_pattern = true;
-    for graph_info in graph_infos {
-        let mut coefficient = find_coefficient(index);
-        index += 1;
+    Ok(graph_infos)
+}
+
+fn generate_morph_rule_using_subtraction(pattern: &str, edge_induced_pattern: &str) -> Result<Vec<(i64, String)>, OptimizerError> {
+    let anti_edges_set = collect_anti_edges_numbers(pattern);
+    let is_edge_induced_pattern = pattern == edge_induced_pattern;
+    let num_nodes = get_num_nodes(pattern);
+    let pattern_info: GraphInfo = parse_input(edge_induced_pattern, num_nodes);
+    call_bliss(&pattern_info, "vertex_induced")?;
+    let peregrine_directory = create_peregrine_run_directory()?;
+    let graph_infos = read_bliss_result_graphs()?;
+    let coefficients = find_super_pattern_coefficients(&graph_infos, &pattern_info.graph, &anti_edges_set, &peregrine_directory, false)?;
+    let mut expansion = vec![];
+    for (graph_info, mut coefficient) in graph_infos.into_iter().zip(coefficients) {
         if coefficient == 0 {
             continue;
         }
@@ -384,408 +313,191 @@ fn generate_morph_rule_using_subtraction(pattern: &str, edge_induced_pattern: &s
             should_write_anti_edges = false;
             
         }
-        let graph_node = graph_to_egraph_node(&graph_info.graph, &pattern_info, should_write_anti_edges, provenance, egraph, &HashSet::new());
-        let count_num = egraph.add(SimpleLanguage::Num(coefficient as i64));
-        let count_node = egraph.add(SimpleLanguage::Count([count_num, graph_node]));
-        if first_pattern {
-            union_id = count_node;
-        }
-        else {
-            union_id = egraph.add(SimpleLanguage::Union([count_node, union_id]));
-        }
-        first_pattern = false;
+        expansion.push((coefficient as i64, graph_to_pattern_string(&graph_info.graph, should_write_anti_edges)));
     }
-    fs::remove_dir_all(&peregrine_directory);
-    union_id
-}
-
-// fn generate_morph_rule_using_subtraction(pattern: &str, edge_induced_pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>) -> Id {
-//     let is_edge_induced_pattern = pattern == edge_induced_pattern;
-//     let num_nodes = get_num_nodes(pattern);
-//     let pattern_info: GraphInfo = parse_input(&edge_induced_pattern, num_nodes);
-//     call_bliss(&pattern_info, "vertex_induced");
-//     let result_directory = DIRECTORY_PATH.to_string() + RESULT_DIRECTORY;
-//     let entries = fs::read_dir(result_directory).unwrap();
-//     let mut index = 0;
-//     let mut union_id = Id::from(0_usize); 
-//     for entry in entries {
-//         let dir_entry = entry.unwrap();
-//         let mut reader = create_file_reader(&dir_entry);
-//         let mut coefficient = 0;
-//         let new_graph_info = read_graph_from_bliss_file(&mut reader, &mut coefficient);
-//         let mut should_write_anti_edges = true;
-//         if !is_edge_induced_pattern && pattern_info.num_edges != new_graph_info.num_edges {
-//             coefficient *= -1;
-            
-//         }
-//         if !is_edge_induced_pattern && pattern_info.num_edges == new_graph_info.num_edges {
-//             should_write_anti_edges = false;
-            
-//         }
-//         let graph_node = graph_to_egraph_node(&new_graph_info.graph, &pattern_info, should_write_anti_edges, provenance, egraph, &HashSet::new());
-//         let count_num = egraph.add(SimpleLanguage::Num(coefficient as i64));
-//         let count_node = egraph.add(SimpleLanguage::Count([count_num, graph_node]));
-//         if index == 0 {
-//             union_id = count_node;
-//         }
-//         else {
-//             union_id = egraph.add(SimpleLanguage::Union([count_node, union_id]));
-//         }
-//         index += 1; 
-//         println!("read one");
-//         fs::remove_file(&dir_entry.path());
-//     }
-//     union_id
-// }
-
-fn generate_morph_rule_using_peregrine(pattern: &str, edge_induced_pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>) -> Id {
+    fs::remove_dir_all(&peregrine_directory).map_err(|error| OptimizerError::io(&peregrine_directory, error))?;
+    Ok(expansion)
+}
+
+
+fn generate_morph_rule_using_peregrine(pattern: &str, edge_induced_pattern: &str) -> Result<Vec<(i64, String)>, OptimizerError> {
     let anti_edges_set = collect_anti_edges_numbers(pattern);
     let num_nodes = get_num_nodes(pattern);
-    let pattern_info: GraphInfo = parse_input(&edge_induced_pattern, num_nodes);
-    call_bliss(&pattern_info, "edge_induced");
-    let result_directory = DIRECTORY_PATH.to_string() + RESULT_DIRECTORY;
-    let peregrine_directory = DIRECTORY_PATH.to_string() + PEREGRINE_DIRECTORY;
-    fs::create_dir(&peregrine_directory);
-    let entries = fs::read_dir(result_directory).unwrap();
-    let mut union_id = Id::from(0_usize); 
-    let mut index = 0;
-    let mut graph_infos = vec![];
-    for entry in entries {
-        index += 1;
-        let dir_entry = entry.unwrap();
-        let mut reader = create_file_reader(&dir_entry);
-        let mut coefficient = 0;
-        let new_graph_info  = read_graph_from_bliss_file(&mut reader, &mut coefficient); 
-        make_peregrine_datagraph(&new_graph_info.graph, index);
-        graph_infos.push(new_graph_info);
-        fs::remove_file(&dir_entry.path());
-    }
-    write_pattern_to_peregrine(&pattern_info.graph, &anti_edges_set, true);
-    let mut index = 1;
-    let mut first_pattern = true;
-    for graph_info in graph_infos {
-        let coefficient = find_coefficient(index);
-        index += 1;
+    let pattern_info: GraphInfo = parse_input(edge_induced_pattern, num_nodes);
+    call_bliss(&pattern_info, "edge_induced")?;
+    let peregrine_directory = create_peregrine_run_directory()?;
+    let graph_infos = read_bliss_result_graphs()?;
+    let coefficients = find_super_pattern_coefficients(&graph_infos, &pattern_info.graph, &anti_edges_set, &peregrine_directory, true)?;
+    let mut expansion = vec![];
+    for (graph_info, coefficient) in graph_infos.into_iter().zip(coefficients) {
         if coefficient == 0 {
             continue;
         }
-        let graph_node = graph_to_egraph_node(&graph_info.graph, &pattern_info, true, provenance, egraph, &HashSet::new());
-        let count_num = egraph.add(SimpleLanguage::Num(coefficient as i64));
-        let count_node = egraph.add(SimpleLanguage::Count([count_num, graph_node]));
-        if first_pattern {
-            union_id = count_node;
-        }
-        else {
-            union_id = egraph.add(SimpleLanguage::Union([count_node, union_id]));
-        }
-        first_pattern = false;
+        expansion.push((coefficient as i64, graph_to_pattern_string(&graph_info.graph, true)));
     }
-    fs::remove_dir_all(&peregrine_directory);
-    union_id
+    fs::remove_dir_all(&peregrine_directory).map_err(|error| OptimizerError::io(&peregrine_directory, error))?;
+    Ok(expansion)
 }
 
-fn find_coefficient(index: i32) -> i32 {
-    let path = DIRECTORY_PATH.to_string() + PEREGRINE_DIRECTORY;
-    let path_clone = path.clone();
-    let output = Command::new("../peregrine-master/bin/count")
-    .arg(path + "data" + &index.to_string())
-    .arg(path_clone + "pattern.txt")
+static PEREGRINE_RUN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// Every expansion gets its own directory under PEREGRINE_DIRECTORY, named by the process id
+// and a per-process counter, so neither concurrent expansions nor concurrent optimizer
+// processes share or remove each other's files.
+fn create_peregrine_run_directory() -> Result<String, OptimizerError> {
+    let run = PEREGRINE_RUN_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let run_directory = format!("{}{}{}-{}/", DIRECTORY_PATH, PEREGRINE_DIRECTORY, process::id(), run);
+    create_directory(&run_directory)?;
+    Ok(run_directory)
+}
+
+// Every super-pattern needs its own convert_data + count run. The runs are independent, so
+// they go to the rayon pool, each in its own job directory under run_directory so
+// concurrent jobs never share data or pattern files. Coefficients come back in the order
+// of graph_infos.
+fn find_super_pattern_coefficients(graph_infos: &Vec<GraphInfo>, pattern: &Vec<Vec<usize>>, anti_edges: &HashSet<(usize, usize)>, run_directory: &str, write_anti_edge: bool) -> Result<Vec<i32>, OptimizerError> {
+    graph_infos.par_iter().enumerate().map(|(index, graph_info)| {
+        let job_directory = run_directory.to_string() + &index.to_string() + "/";
+        create_directory(&job_directory)?;
+        make_peregrine_datagraph(&graph_info.graph, &job_directory)?;
+        write_pattern_to_peregrine(pattern, anti_edges, write_anti_edge, &job_directory)?;
+        let coefficient = find_coefficient(&job_directory)?;
+        fs::remove_dir_all(&job_directory).map_err(|error| OptimizerError::io(&job_directory, error))?;
+        Ok(coefficient)
+    }).collect()
+}
+
+// peregrine prints the count on its last line of output.
+fn find_coefficient(job_directory: &str) -> Result<i32, OptimizerError> {
+    let output = Command::new(PEREGRINE_COUNT_PATH)
+    .arg(job_directory.to_string() + "data")
+    .arg(job_directory.to_string() + "pattern.txt")
     .output()
-    .expect("Failed to execute command");
-    let stdout = str::from_utf8(&output.stdout).expect("Invalid UTF-8 sequence");
-    stdout.lines().last().unwrap().parse().unwrap()
+    .map_err(|error| OptimizerError::io(PEREGRINE_COUNT_PATH, error))?;
+    if !output.status.success() {
+        return Err(OptimizerError::backend(PEREGRINE_COUNT_PATH, &output.stderr));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let last_line = stdout.lines().last().unwrap_or("").trim();
+    match last_line.parse() {
+        Ok(coefficient) => Ok(coefficient),
+        Err(_) => Err(OptimizerError::backend(PEREGRINE_COUNT_PATH, format!("unexpected output '{}'", last_line).as_bytes())),
+    }
 }
 
-fn make_peregrine_datagraph(graph: &Vec<Vec<usize>>, index: i32) {
-    let path = DIRECTORY_PATH.to_string() + PEREGRINE_DIRECTORY;
-    let path_clone = path.clone();
-    let file_path = path + &index.to_string() + ".txt";
-    let mut output = OpenOptions::new().write(true).create(true).truncate(true).open(&file_path).unwrap();
+fn make_peregrine_datagraph(graph: &Vec<Vec<usize>>, job_directory: &str) -> Result<(), OptimizerError> {
+    let file_path = job_directory.to_string() + "graph.txt";
+    let mut output = OpenOptions::new().write(true).create(true).truncate(true).open(&file_path).map_err(|error| OptimizerError::io(&file_path, error))?;
     for i in 0..graph.len() {
         for j in i+1..graph.len() {
             if graph[i][j] == 1 {
-                writeln!(output, "{} {}", i+1, j+1);
+                writeln!(output, "{} {}", i+1, j+1).map_err(|error| OptimizerError::io(&file_path, error))?;
             }
         }
     }
-    Command::new("../peregrine-master/bin/convert_data")
+    let output = Command::new(PEREGRINE_CONVERT_PATH)
     .arg(file_path)
-    .arg(path_clone + "data" + &index.to_string())
-    .output();
+    .arg(job_directory.to_string() + "data")
+    .output()
+    .map_err(|error| OptimizerError::io(PEREGRINE_CONVERT_PATH, error))?;
+    if !output.status.success() {
+        return Err(OptimizerError::backend(PEREGRINE_CONVERT_PATH, &output.stderr));
+    }
+    Ok(())
 }
 
-fn write_pattern_to_peregrine(graph: &Vec<Vec<usize>>, anti_edges: &HashSet<(usize, usize)>, write_anti_edge: bool) {
-    let path = DIRECTORY_PATH.to_string() + PEREGRINE_DIRECTORY;
-    let mut output = OpenOptions::new().write(true).create(true).truncate(true).open(path + "pattern.txt").unwrap();
+fn write_pattern_to_peregrine(graph: &Vec<Vec<usize>>, anti_edges: &HashSet<(usize, usize)>, write_anti_edge: bool, job_directory: &str) -> Result<(), OptimizerError> {
+    let file_path = job_directory.to_string() + "pattern.txt";
+    let mut output = OpenOptions::new().write(true).create(true).truncate(true).open(&file_path).map_err(|error| OptimizerError::io(&file_path, error))?;
     for i in 0..graph.len() {
         for j in i+1..graph.len() {
             if graph[i][j] == 1 {
-                writeln!(output, "{} {}", i+1, j+1);
+                writeln!(output, "{} {}", i+1, j+1).map_err(|error| OptimizerError::io(&file_path, error))?;
             }
             else if write_anti_edge && anti_edges.contains(&(i+1, j+1)) || anti_edges.contains(&(j+1, i+1)) {
-                writeln!(output, "{} {} {}", i+1, j+1, 1);
+                writeln!(output, "{} {} {}", i+1, j+1, 1).map_err(|error| OptimizerError::io(&file_path, error))?;
             }
         }
     }
+    Ok(())
 }
 
-// fn generate_morph_rule_using_super_pattern(pattern: &str, edge_induced_pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>) -> Id {
-//     let anti_edges_set = collect_anti_edges_numbers(pattern);
-//     call_bliss(&pattern_info, "permutation");
-//     let result_directory = DIRECTORY_PATH.to_string() + RESULT_DIRECTORY;
-//     let entries = fs::read_dir(result_directory).unwrap();
-//     let mut index = 0;
-//     let mut union_id = Id::from(0_usize); 
-//     for entry in entries {
-//         let anti_edges_set_clone = anti_edges_set.clone();
-//         let dir_entry = entry.unwrap();
-//         let mut reader = create_file_reader(&dir_entry);
-//         let mut coefficient = 0;
-//         let (new_graph_info, node_permutations) = read_graph_from_bliss_file(&mut reader, &mut coefficient);
-//         let graph_node_string = create_permuted_pattern_for_moprh_rule(&(new_graph_info, anti_edges_set_clone), &node_permutations);
-//         let graph_node_string_recexpr: RecExpr<SimpleLanguage> = graph_node_string.parse().unwrap(); 
-//         push_to_global_patterns_vec(graph_node_string);
-//         let graph_match_node = egraph.add_expr(&graph_node_string_recexpr);
-//         let graph_node = egraph.add(SimpleLanguage::Morph([*provenance, graph_match_node]));
-//         let count_num = egraph.add(SimpleLanguage::Num(coefficient));
-//         let count_node = egraph.add(SimpleLanguage::Count([count_num, graph_node]));
-//         if index == 0 {
-//             union_id = count_node;
-//         }
-//         else {
-//             union_id = egraph.add(SimpleLanguage::Union([count_node, union_id]));
-//         }
-//         index += 1; 
-//         fs::remove_file(&dir_entry.path());
-//     }
-//     union_id
-// }
-
-fn generate_morph_rewrite_rule(pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>) -> Id {
+
+pub fn generate_morph_rewrite_rule(pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, Optimizer>) -> Result<Option<Id>, OptimizerError> {
+    let expansion = find_morph_expansion(pattern, &mut egraph.analysis)?;
+    Ok(add_morph_expansion(&expansion, provenance, egraph))
+}
+
+// The expansion only depends on the isomorphism class of the pattern, so it is looked up by
+// canonical form first and only computed through bliss and peregrine on a miss.
+fn find_morph_expansion(pattern: &str, optimizer: &mut Optimizer) -> Result<Vec<(i64, String)>, OptimizerError> {
+    let canonical_pattern = canonical_pattern_string(pattern, None);
+    if let Some(expansion) = optimizer.get_memoized_morph_expansion(&canonical_pattern) {
+        return Ok(expansion);
+    }
     let super_pattern = convert_to_edge_induced_pattern(&pattern.to_string(), true);
     let num_nodes = get_num_nodes(pattern);
     let super_pattern_info = parse_input(&super_pattern, num_nodes);
     let edge_induced_pattern = convert_to_edge_induced_pattern(&pattern.to_string(), false);
     let is_edge_induced_pattern = pattern == edge_induced_pattern;
-    if is_graph_complete(super_pattern_info.num_edges, super_pattern_info.graph.len() as i32) || is_edge_induced_pattern {
-        generate_morph_rule_using_subtraction(pattern, &edge_induced_pattern, provenance, egraph)
+    let expansion = if is_graph_complete(super_pattern_info.num_edges, super_pattern_info.graph.len() as i32) || is_edge_induced_pattern {
+        generate_morph_rule_using_subtraction(pattern, &edge_induced_pattern)?
     }
     else {
-        generate_morph_rule_using_peregrine(pattern, &edge_induced_pattern, provenance, egraph)
+        generate_morph_rule_using_peregrine(pattern, &edge_induced_pattern)?
+    };
+    optimizer.memoize_morph_expansion(canonical_pattern, expansion.clone())?;
+    Ok(expansion)
+}
+
+// The super-patterns come labelled the way bliss wrote them and are brought into the
+// native canonical form before they enter the e-graph.
+pub fn add_morph_expansion(expansion: &Vec<(i64, String)>, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, Optimizer>) -> Option<Id> {
+    let mut terms = vec![];
+    for (coefficient, super_pattern) in expansion {
+        let super_pattern = canonical_pattern_string(super_pattern, None);
+        egraph.analysis.register_pattern(super_pattern.to_string());
+        let super_pattern_recexpr: RecExpr<SimpleLanguage> = super_pattern.parse().unwrap();
+        let super_pattern_id = egraph.add_expr(&super_pattern_recexpr);
+        let graph_node = egraph.add(SimpleLanguage::Morph([*provenance, super_pattern_id]));
+        terms.push((*coefficient, graph_node));
     }
-    
+    add_sum(egraph, terms)
 }
 
-// fn generate_morph_rewrite_rule(pattern: &str, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>) -> Id {
-//     let edge_induced_pattern = convert_to_edge_induced_pattern(&pattern.to_string(), false);
-//     let is_edge_induced_pattern = pattern == edge_induced_pattern;
-//     let pattern_info: GraphInfo = parse_input(&edge_induced_pattern);
-//     call_bliss(&pattern_info);
-//     let result_directory = DIRECTORY_PATH.to_string() + RESULT_DIRECTORY;
-//     let entries = fs::read_dir(result_directory).unwrap();
-//     let mut index = 0;
-//     let mut union_id = Id::from(0_usize); 
-//     for entry in entries {
-//         let dir_entry = entry.unwrap();
-//         let mut reader = create_file_reader(&dir_entry);
-//         let mut coefficient = 0;
-//         let new_graph_info = read_graph_from_bliss_file(&mut reader, &mut coefficient);
-//         let graph_node = graph_to_egraph_node(&new_graph_info.graph, &pattern_info, is_edge_induced_pattern, provenance, egraph);
-//         if !is_edge_induced_pattern && pattern_info.num_edges != new_graph_info.num_edges {
-//             coefficient *= -1;
-//         }
-//         let count_num = egraph.add(SimpleLanguage::Num(coefficient));
-//         let count_node = egraph.add(SimpleLanguage::Count([count_num, graph_node]));
-//         if index == 0 {
-//             union_id = count_node;
-//         }
-//         else {
-//             union_id = egraph.add(SimpleLanguage::Union([count_node, union_id]));
-//         }
-//         index += 1; 
-//         fs::remove_file(&dir_entry.path());
-//     }
-//     union_id
-// }
-
-
-
-
-
-fn graph_to_egraph_node(graph: &Vec<Vec<usize>>, original_pattern: &GraphInfo, should_write_anti_edges: bool, provenance: &Id, egraph: &mut EGraph<SimpleLanguage, ()>, anti_edges: &HashSet<(String, String)>) -> Id {
-    let mut graph_enodes = vec![];
+
+fn graph_to_pattern_string(graph: &Vec<Vec<usize>>, should_write_anti_edges: bool) -> String {
+    let mut pattern = "(Match".to_string();
     for i in 0..graph.len() {
         for j in i+1..graph.len() {
             let u = number_to_alphabet(i + 1);
             let v = number_to_alphabet(j + 1);
-            if anti_edges.contains(&(u.to_string(), v.to_string())) || anti_edges.contains(&(v.to_string(), u.to_string())) {
-                let unode = egraph.add(SimpleLanguage::Symbol(Symbol::from(u)));
-                let vnode = egraph.add(SimpleLanguage::Symbol(Symbol::from(v)));
-                let anti_edge = egraph.add(SimpleLanguage::AntiEdge([unode, vnode]));
-                graph_enodes.push(anti_edge);
-            }
-            else if graph[i][j] == 1 {
-                let unode = egraph.add(SimpleLanguage::Symbol(Symbol::from(u)));
-                let vnode = egraph.add(SimpleLanguage::Symbol(Symbol::from(v)));
-                let edge = egraph.add(SimpleLanguage::Edge([unode, vnode]));
-                graph_enodes.push(edge);
+            if graph[i][j] == 1 {
+                pattern += &format!(" (-- {} {})", u, v);
             }
-            else if !should_write_anti_edges {
-                continue;
-            }
-            else {
-                let unode = egraph.add(SimpleLanguage::Symbol(Symbol::from(u)));
-                let vnode = egraph.add(SimpleLanguage::Symbol(Symbol::from(v)));
-                let anti_edge = egraph.add(SimpleLanguage::AntiEdge([unode, vnode]));
-                graph_enodes.push(anti_edge);
+            else if should_write_anti_edges {
+                pattern += &format!(" (!- {} {})", u, v);
             }
         }
     }
-    let match_enode = SimpleLanguage::Match(graph_enodes.into_boxed_slice());
-    let match_enode_clone = match_enode.clone();
-    let graph_match_node = egraph.add(match_enode);
-    let get_node = |id| egraph[id].nodes[0].clone();
-    let pattern = match_enode_clone.build_recexpr(get_node); 
-    let pattern_string = format!("{}", pattern);
-    push_to_global_patterns_vec(pattern_string);
-    egraph.add(SimpleLanguage::Morph([*provenance, graph_match_node]))
+    pattern += ")";
+    pattern
 }
 
 
 fn calculate_permutation(n: u64, i: u64) -> u64 {
-    let result = n.factorial() / ((n - i).factorial() * i.factorial());
-    result
+    n.factorial() / ((n - i).factorial() * i.factorial())
 }
  
 
-fn get_node_degree(pattern: &Vec<Vec<usize>>, node: usize) -> usize {
-    let mut degree = 0;
-    for i in 0..pattern.len() {
-        if pattern[node][i] == 1 {
-            degree += 1;
-        }
-    }
-    degree
-}
-
-fn count_fragments(graph: &Vec<Vec<usize>>, pattern: &Vec<Vec<usize>>) -> u64 {
-    let cut_set = min_cut(pattern);
-    let deg1 = get_node_degree(pattern, cut_set[0].0) - 1;
-    let deg2 = get_node_degree(pattern, cut_set[0].1) - 1;
-    let mut result = 0;
-    for i in 0..graph.len() {
-        for j in 0..graph.len() {
-            if i == j {
-                continue;
-            }
-            let deg_node = get_node_degree(graph, i) - 1;
-            let deg_neighbor = get_node_degree(graph, j) - 1;
-            if deg_node >= deg1 && deg_neighbor >= deg2 {
-                result += calculate_permutation(deg_node as u64, deg1 as u64) * calculate_permutation(deg_neighbor as u64, deg2 as u64);
-            }
-        }
-    }
-    result
-}
-
-struct ShrinkageInfo {
-    num: i32,
-    pattern: String,
-}
-
-
-fn bfs(s: usize, t: usize, parent: &mut Vec<usize>, graph: &mut Vec<Vec<usize>>) -> bool {
-    let mut visited = vec![false; graph.len()];
-    let mut queue = VecDeque::new();
-    queue.push_back(s);
-    visited[s] = true;
-    while queue.len() != 0 {
-        let mut u = queue.pop_front().unwrap();
-        for ind in 0..graph.len() {
-            if !visited[ind] && graph[u][ind] > 0 {
-                queue.push_back(ind);
-                visited[ind] = true;
-                parent[ind] = u;
-            }
-        }
-    }
-    visited[t]
-}
-
-fn dfs(graph: &mut Vec<Vec<usize>>, s: usize, visited: &mut Vec<bool>) {
-    visited[s] = true;
-    for i in 0..graph.len() {
-        if graph[s][i] > 0 && !visited[i] {
-            dfs(graph, i, visited);
-        }
-    }
-}
-
-fn min_cut(original_graph: &Vec<Vec<usize>>) -> Vec<(usize, usize)>{
-    let mut graph = original_graph.clone();
-    let mut src = 0;
-    let mut sink = graph.len() - 1;
-    let mut parent = vec![0; graph.len()];
-    let mut max_flow = 0;
-    let mut s = sink;
-    while bfs(src, sink, &mut parent, &mut graph) {
-        let mut path_flow = usize::MAX;
-        s = sink;
-        while s != src {
-            path_flow = cmp::min(path_flow, graph[parent[s]][s]);
-            s = parent[s];
-        }
-        max_flow += path_flow;
-        let mut v = sink;
-        while v != src {
-            let mut u = parent[v];
-            graph[u][v] -= path_flow;
-            graph[v][u] += path_flow;
-            v = parent[v];
-        }
-    }
-    let mut visited = vec![false; graph.len()];
-    let mut cut_set = vec![];
-    dfs(&mut graph, s, &mut visited);
-    for i in 0..graph.len() {
-        for j in 0..graph.len() {
-            if graph[i][j] == 0 && original_graph[i][j] > 0 && visited[i] {
-                cut_set.push((i, j));
-            }
-        }
-    }
-    cut_set
-}
-
-
-fn call_bliss(pattern_info: &GraphInfo, message: &str) {
+pub fn call_bliss(pattern_info: &GraphInfo, message: &str) -> Result<(), OptimizerError> {
     let bliss_graph = make_graph_file(pattern_info, true);
-    write_to_file(bliss_graph, DIRECTORY_PATH.to_string() + SRC_GRAPH_BLISS_FILE);
-    write_to_pipe(BLISS_PIPE_NAME, message.to_string());
-    get_done_signal();
-}
-
-fn build_escape_rewrite_rule_string(num_fragments: &u64, shrinkage_info: &ShrinkageInfo) -> String {
-    let mut result = "(+ ".to_string();
-    result += &num_fragments.to_string();
-    result += " (Count ";
-    result += &shrinkage_info.num.to_string();
-    result += " ";
-    result += &shrinkage_info.pattern;
-    result += "))";
-    result
+    write_to_file(bliss_graph, DIRECTORY_PATH.to_string() + SRC_GRAPH_BLISS_FILE)?;
+    write_to_pipe(BLISS_PIPE_NAME, message.to_string())?;
+    get_done_signal()
 }
 
-fn escape_rewrite_rule(input_graph_info: &GraphInfo, input_pattern_info: &GraphInfo, shrinkage_info: &ShrinkageInfo) -> Pattern<SimpleLanguage> {
-    let num_fragments = count_fragments(&input_graph_info.graph, &input_pattern_info.graph);
-    let escape_rule = build_escape_rewrite_rule_string(&num_fragments, &shrinkage_info);
-    println!();
-    println!("Escape rule");
-    println!("{}", escape_rule);
-    escape_rule.parse().unwrap()
-}
-
-fn convert_to_edge_induced_pattern(pattern: &String, convert_anti_edge_to_edge: bool) -> String {
+pub fn convert_to_edge_induced_pattern(pattern: &String, convert_anti_edge_to_edge: bool) -> String {
     if !convert_anti_edge_to_edge {
         let anti_edge_string_pattern = Regex::new(r" \(!- [a-zA-Z]+ [a-zA-Z]+\)").unwrap();
         anti_edge_string_pattern.replace_all(pattern, "").to_string()
@@ -794,26 +506,7 @@ fn convert_to_edge_induced_pattern(pattern: &String, convert_anti_edge_to_edge:
     }
 }
 
-fn get_user_rules() -> HashMap<String, String> {
-    let mut user_rules = HashMap::new();
-    let rule1_lhs = "(Match (-- a b) (-- a d) (-- a c) (-- b c))".to_string();
-    let rule1_rhs = "(Match (-- a b) (-- a d) (-- a c) (-- b c))".to_string();
-    user_rules.insert(rule1_lhs, rule1_rhs);
-    user_rules
-}
-
-
-// fn get_edges(pattern: &String) -> Vec<(String, String)> {
-//     println!("{pattern}");
-//     let edge_regex = Regex::new(r"--\s+(\w+)\s+(\w+)").unwrap();
-//     let mut edges = Vec::new();
-//     for cap in edge_regex.captures_iter(&pattern) {
-//        edges.push((cap[1].to_string(), cap[2].to_string()));
-//     }
-//     edges
-// }
-
-fn parse_pattern(pattern: &String, edges: &mut Vec<(String, String)>, anti_edges: &mut Vec<(String, String)>) {
+pub fn parse_pattern(pattern: &String, edges: &mut Vec<(String, String)>, anti_edges: &mut Vec<(String, String)>) {
     let new_pattern: String = pattern.chars()
                                    .filter(|&c| c != '(' && c != ')')
                                    .collect();
@@ -828,8 +521,22 @@ fn parse_pattern(pattern: &String, edges: &mut Vec<(String, String)>, anti_edges
     }
 }
 
+pub fn parse_not_equal_pairs(pattern: &str) -> Vec<(String, String)> {
+    let new_pattern: String = pattern.chars()
+                                   .filter(|&c| c != '(' && c != ')')
+                                   .collect();
+    let parts: Vec<&str> = new_pattern.split_whitespace().collect();
+    let mut not_equal_pairs = vec![];
+    for i in 0..parts.len() {
+        if parts[i] == "<>" {
+            not_equal_pairs.push((parts[i + 1].to_string(), parts[i + 2].to_string()));
+        }
+    }
+    not_equal_pairs
+}
+
 fn update_node_map(node_map: &mut HashMap<String, i32>, edge_number:i32, key: &String) -> i32 {
-    let mut edge_number_cpy = edge_number.clone();
+    let mut edge_number_cpy = edge_number;
     if !node_map.contains_key(key) {
         node_map.insert(key.to_string(), edge_number);
         edge_number_cpy += 1;
@@ -838,8 +545,7 @@ fn update_node_map(node_map: &mut HashMap<String, i32>, edge_number:i32, key: &S
 }
 
 
-
-fn make_morph_graph_file(pattern: &String, file_number: i32) {
+fn make_morph_graph_file(pattern: &String, file_number: i32) -> Result<(), OptimizerError> {
     let mut edges = vec![];
     let mut anti_edges = vec![];
     parse_pattern(pattern, &mut edges, &mut anti_edges);
@@ -861,91 +567,153 @@ fn make_morph_graph_file(pattern: &String, file_number: i32) {
         file_lines.push(new_line);
     }
     let path = DIRECTORY_PATH.to_string() + COST_DIRECTORY + &file_number.to_string() + ".txt";
-    write_to_file(file_lines, path);
+    write_to_file(file_lines, path)
 }
 
-fn find_patterns_costs() {
-    let mut index = 0;
+fn find_patterns_costs(optimizer: &mut Optimizer, data_graph: &DataGraph) -> Result<(), OptimizerError> {
     let path = DIRECTORY_PATH.to_string() + COST_DIRECTORY;
-    fs::create_dir(path);
-    unsafe {
-        let patterns = get_global_patterns_vec();
-        for pattern in patterns {
-            if (pattern.chars().next().unwrap() == 'F') {
-                let mut new_pattern = "(Match (-- a b) (-- b c) (-- a c))";
-                if *pattern == "F7".to_string() {
-                    new_pattern = "(Match (-- a b) (-- b c) (-- c d) (-- a d))";
-                }
-                make_morph_graph_file(&new_pattern.to_string(), index); 
-            }
-            else {
-                make_morph_graph_file(pattern, index);
-            }
-            index += 1;
+    create_directory(&path)?;
+    for (index, pattern) in optimizer.patterns.iter().enumerate() {
+        make_morph_graph_file(pattern, index as i32)?;
+    }
+    write_data_graph_edge_list(data_graph, DIRECTORY_PATH.to_string() + COST_DIRECTORY + DATA_GRAPH_EDGE_LIST)?;
+    write_to_pipe(MORPH_PIPE_NAME, "start".to_string())?;
+    read_from_pipe(MORPH_PIPE_NAME)?;
+    get_costs(optimizer)?;
+    find_formulas_costs(optimizer, data_graph);
+    find_complements_costs(optimizer, data_graph);
+    find_homs_costs(optimizer, data_graph);
+    Ok(())
+}
+
+// Const formulas are priced by what evaluating them takes, from the graph size and the
+// estimated costs of the triangle and 4-cycle patterns. The formula's own entry only holds
+// its loops, every statistic it reads has an entry of its own (see get_formula_statistics).
+pub fn find_formulas_costs(optimizer: &mut Optimizer, data_graph: &DataGraph) {
+    let mut costs = vec![];
+    for formula in optimizer.formulas.iter() {
+        costs.push((formula.clone(), get_formula_loop_cost(formula, data_graph, &optimizer.cost_map)));
+        for statistic in get_formula_statistics(formula) {
+            costs.push((statistic.to_string(), get_statistic_cost(statistic, data_graph, &optimizer.cost_map)));
         }
     }
-    write_to_pipe(MORPH_PIPE_NAME, "start".to_string());
-    read_from_pipe(MORPH_PIPE_NAME);
-    get_costs();
+    optimizer.cost_map.extend(costs);
+}
+
+// Complement terms are matched by the native engine on the data graph, see get_complement_cost.
+pub fn find_complements_costs(optimizer: &mut Optimizer, data_graph: &DataGraph) {
+    let costs: Vec<(String, f64)> = optimizer.complements.iter()
+        .map(|pattern| ("(Complement ".to_string() + pattern + ")", get_complement_cost(pattern, data_graph)))
+        .collect();
+    optimizer.cost_map.extend(costs);
 }
 
-fn get_costs() {
+// Hom terms are summed out by the native engine, see get_hom_cost.
+pub fn find_homs_costs(optimizer: &mut Optimizer, data_graph: &DataGraph) {
+    let costs: Vec<(String, f64)> = optimizer.homs.iter()
+        .map(|pattern| ("(Hom ".to_string() + pattern + ")", get_hom_cost(pattern, data_graph)))
+        .collect();
+    optimizer.cost_map.extend(costs);
+}
+
+// The cost estimator answers with one cost per line, in the order the pattern files were written.
+fn get_costs(optimizer: &mut Optimizer) -> Result<(), OptimizerError> {
     let path = DIRECTORY_PATH.to_string() + COST_DIRECTORY + "result.txt";
-    let file = File::open(path).unwrap();
-    let reader = BufReader::new(file);
-    unsafe {
-        let map = get_global_map();
-        let patterns = get_global_patterns_vec();
-        let mut index = 0;
-        for line in reader.lines() {
-            let line = line.unwrap();
-            let cost = line.trim().parse::<f64>().unwrap();
-            let pattern = patterns[index].clone();
-            map.insert(pattern, cost);
-            index += 1;
-        }
+    let lines = read_lines(&path)?;
+    if lines.len() < optimizer.patterns.len() {
+        return Err(OptimizerError::parse(&path, lines.len() + 1, 1, &format!("expected {} costs, found {}", optimizer.patterns.len(), lines.len())));
+    }
+    for index in 0..optimizer.patterns.len() {
+        let cost = parse_field::<f64>(&lines[index], 0, &path, index + 1)?;
+        let pattern = optimizer.patterns[index].clone();
+        optimizer.cost_map.insert(pattern, cost);
     }
+    Ok(())
 }
 
-fn create_pipes() {
-    create_pipe(BLISS_PIPE_NAME);
-    create_pipe(MORPH_PIPE_NAME);
+pub fn create_pipes() -> Result<(), OptimizerError> {
+    create_pipe(BLISS_PIPE_NAME)?;
+    create_pipe(MORPH_PIPE_NAME)
 }
 
-fn read_from_pipe(pipe_name: &str) {
+fn read_from_pipe(pipe_name: &str) -> Result<(), OptimizerError> {
     let path = DIRECTORY_PATH.to_string() + pipe_name;
-    let mut file = File::open(path).unwrap();
+    File::open(&path).map_err(|error| OptimizerError::io(&path, error))?;
+    Ok(())
 }
 
-fn initialize_num_patterns(num: usize) {
-    let mut num_patterns = NUM_PATTERNS.lock().unwrap();
-    *num_patterns = num;
+// `--threads n` sizes the rayon pool used for the data graph computations, by default
+// rayon takes one thread per core.
+pub fn initialize_thread_pool(args: &Vec<String>) -> Result<(), OptimizerError> {
+    if let Some(num_threads) = get_flag_value(args, "--threads") {
+        let num_threads: usize = num_threads.parse()
+            .map_err(|_| OptimizerError::Argument(format!("--threads expects a number of threads, got '{}'", num_threads)))?;
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global()
+            .map_err(|error| OptimizerError::Argument(format!("--threads: {}", error)))?;
+    }
+    Ok(())
 }
 
-fn parse_alt_patterns_string(input: String) -> HashMap<String, i32> {
+pub fn parse_alt_patterns_string(input: String) -> Result<HashMap<String, i32>, OptimizerError> {
     let mut patterns = HashMap::new();
+    // a Div divisor
+    if !input.starts_with('(') {
+        return Ok(patterns);
+    }
     let (key_word, rest) = input.split_once(' ').unwrap();
     match key_word {
-        "(Union" => {
-            let(left, right) = parse_union_string(rest.to_string());
-            patterns = parse_alt_patterns_string(left);
-            let second_set = parse_alt_patterns_string(right);
-            for (pattern, num) in second_set {
-                patterns.entry(pattern).and_modify(|value| *value += num).or_insert(num);
+        "(Mul" | "(Sub" | "(Div" | "(Join" => {
+            for operand in parse_operands_string(rest.to_string()) {
+                for (pattern, count) in parse_alt_patterns_string(operand)? {
+                    patterns.entry(pattern).and_modify(|value| *value += count).or_insert(count);
+                }
+            }
+        },
+        "(Sum" => {
+            for (_num, term) in parse_sum_string(rest.to_string())? {
+                for (pattern, count) in parse_alt_patterns_string(term)? {
+                    patterns.entry(pattern).and_modify(|value| *value += count).or_insert(count);
+                }
             }
         },
         "(Count" => {
-            let (num, right) = parse_count_string(rest.to_string());
-            patterns = parse_alt_patterns_string(right);
+            let (_num, right) = parse_count_string(rest.to_string())?;
+            patterns = parse_alt_patterns_string(right)?;
         },
         "(Morph" => {
-            let (provenance, pattern) = parse_morph_string(rest.to_string());
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            patterns.entry(pattern)
+            .and_modify(|value| *value += 1) 
+            .or_insert(1);
+        },
+        "(Hom" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            patterns.entry("(Hom ".to_string() + &pattern + ")")
+            .and_modify(|value| *value += 1)
+            .or_insert(1);
+        },
+        "(Complement" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            patterns.entry("(Complement ".to_string() + &pattern + ")")
+            .and_modify(|value| *value += 1)
+            .or_insert(1);
+        },
+        "(Local" => {
+            let (_provenance, _anchor, pattern) = parse_local_string(rest.to_string());
             patterns.entry(pattern)
             .and_modify(|value| *value += 1) 
             .or_insert(1);
         },
         "(Const" => {
-            let (provenance, formula) = parse_const_string(rest.to_string());
+            // the statistics are shared between formulas, so they are alternatives of their own
+            let (_provenance, formula) = parse_const_string(rest.to_string());
+            for statistic in get_formula_statistics(&formula) {
+                patterns.entry(statistic.to_string())
+                .and_modify(|value| *value += 1)
+                .or_insert(1);
+            }
             patterns.entry(formula)
             .and_modify(|value| *value += 1) 
             .or_insert(1);
@@ -954,168 +722,222 @@ fn parse_alt_patterns_string(input: String) -> HashMap<String, i32> {
 
     };
     
-    patterns
+    Ok(patterns)
 }
 
-fn change_alt_patterns_cost(alt_patterns: &HashMap<String, i32>) {
-    unsafe {
-        let cost_map = get_global_map();
-        for (pattern, num) in alt_patterns {
-            cost_map.insert(pattern.to_string(), 0.0);
-        }
+pub fn change_alt_patterns_cost(alt_patterns: &HashMap<String, i32>, cost_map: &mut HashMap<String, f64>) {
+    for pattern in alt_patterns.keys() {
+        cost_map.insert(pattern.to_string(), 0.0);
     }
 }
 
-fn simplify_pattern_formula(input: String) -> (HashMap<String, i32>) {
-    let mut patterns_count_map = HashMap::new();
-    let mut constant:i64 = 0;
-    let (key_word, rest) = input.split_once(' ').unwrap();
+// The formula as a polynomial over its terms, term (or product of terms) -> coefficient.
+// A division that doesn't cancel stays one opaque term.
+pub fn simplify_pattern_formula(input: String) -> Result<HashMap<String, i64>, OptimizerError> {
+    let overflow = || OptimizerError::Evaluation(format!("{} overflows", input));
+    let mut patterns_count_map: HashMap<String, i64> = HashMap::new();
+    let (key_word, rest) = input.split_once(' ').ok_or(OptimizerError::Evaluation(format!("can't simplify {}", input)))?;
     match key_word {
-        "(Union" => {
-            let(left, right) = parse_union_string(rest.to_string());
-            patterns_count_map = simplify_pattern_formula(left);
-            let second_map = simplify_pattern_formula(right);
-            for (pattern, count) in second_map {
-                patterns_count_map.entry(pattern)
-                .and_modify(|v| *v += count)
-                .or_insert(count);
+        "(Sub" => {
+            let operands = parse_operands_string(rest.to_string());
+            patterns_count_map = simplify_pattern_formula(operands[0].clone())?;
+            for (pattern, count) in simplify_pattern_formula(operands[1].clone())? {
+                add_pattern_count(&mut patterns_count_map, pattern, count.checked_neg().ok_or_else(overflow)?).ok_or_else(overflow)?;
+            }
+        },
+        "(Mul" => {
+            let operands = parse_operands_string(rest.to_string());
+            let left_map = simplify_pattern_formula(operands[0].clone())?;
+            let right_map = simplify_pattern_formula(operands[1].clone())?;
+            for (left, left_count) in left_map.iter() {
+                for (right, right_count) in right_map.iter() {
+                    let (first, second) = if left <= right { (left, right) } else { (right, left) };
+                    let product = "(Mul ".to_string() + first + " " + second + ")";
+                    add_pattern_count(&mut patterns_count_map, product, left_count.checked_mul(*right_count).ok_or_else(overflow)?).ok_or_else(overflow)?;
+                }
+            }
+        },
+        "(Div" => {
+            let operands = parse_operands_string(rest.to_string());
+            let divisor = parse_coefficient(&operands[1])?;
+            let dividend_map = simplify_pattern_formula(operands[0].clone())?;
+            if divisor != 0 && dividend_map.values().all(|count| count % divisor == 0) {
+                for (pattern, count) in dividend_map {
+                    patterns_count_map.insert(pattern, count.checked_div(divisor).ok_or_else(overflow)?);
+                }
+            }
+            else {
+                patterns_count_map.insert("(Div ".to_string() + &pattern_formula_to_string(&dividend_map) + " " + &operands[1] + ")", 1);
+            }
+        },
+        "(Sum" => {
+            for (num, term) in parse_sum_string(rest.to_string())? {
+                for (pattern, count) in simplify_pattern_formula(term)? {
+                    add_pattern_count(&mut patterns_count_map, pattern, num.checked_mul(count).ok_or_else(overflow)?).ok_or_else(overflow)?;
+                }
             }
         },
         "(Count" => {
-            let (num, right) = parse_count_string(rest.to_string());
-            patterns_count_map = simplify_pattern_formula(right);
-            for (key, count) in patterns_count_map.iter_mut() {
-                *count *= num;
+            let (num, right) = parse_count_string(rest.to_string())?;
+            patterns_count_map = simplify_pattern_formula(right)?;
+            for (_key, count) in patterns_count_map.iter_mut() {
+                *count = count.checked_mul(num).ok_or_else(overflow)?;
             }
         },
         "(Morph" => {
-            let (provenance, pattern) = parse_morph_string(rest.to_string());
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
             patterns_count_map.insert(pattern, 1);
         },
+        "(Hom" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            patterns_count_map.insert("(Hom ".to_string() + &pattern + ")", 1);
+        },
+        "(Complement" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            patterns_count_map.insert("(Complement ".to_string() + &pattern + ")", 1);
+        },
+        "(Local" => {
+            let (_provenance, anchor, pattern) = parse_local_string(rest.to_string());
+            patterns_count_map.insert("(Local ".to_string() + &anchor + " " + &pattern + ")", 1);
+        },
+        "(Join" => {
+            patterns_count_map.insert(get_join_key(rest), 1);
+        },
         "(Const" => {
-            let (provenance, formula) = parse_const_string(rest.to_string());
+            let (_provenance, formula) = parse_const_string(rest.to_string());
             patterns_count_map.insert(formula, 1);
         },
         _ => {},
 
     };
     
-    patterns_count_map
+    Ok(patterns_count_map)
+}
+
+// Adds count to the coefficient of pattern, None when that overflows.
+pub fn add_pattern_count(patterns_count_map: &mut HashMap<String, i64>, pattern: String, count: i64) -> Option<()> {
+    let value = patterns_count_map.entry(pattern).or_insert(0);
+    *value = value.checked_add(count)?;
+    Some(())
 }
 
-fn pattern_formula_to_string(pattens_map: &HashMap<String, i32>) -> String {
-    let mut formula_string = String::new();
-    let mut iteration = 0;
+// A Join of two Local terms is one term, keyed by the keys of its sides.
+fn get_join_key(rest: &str) -> String {
+    let keys: Vec<String> = parse_operands_string(rest.to_string()).iter().map(|operand| {
+        let (_provenance, anchor, pattern) = parse_local_string(operand.split_once(' ').unwrap().1.to_string());
+        "(Local ".to_string() + &anchor + " " + &pattern + ")"
+    }).collect();
+    "(Join ".to_string() + &keys.join(" ") + ")"
+}
+
+pub fn pattern_formula_to_string(pattens_map: &HashMap<String, i64>) -> String {
+    let mut formula_string = "(Sum".to_string();
     for (pattern, count) in pattens_map {
-        let pattern_string = "(Count ".to_string() + &count.to_string() + " " + pattern + ")";
-        if iteration == 0 {
-            formula_string = pattern_string;
-        } 
-        else {
-            formula_string = "(Union ".to_string() + &formula_string + " " + &pattern_string + ")";
-        }
-        iteration += 1;
+        formula_string = formula_string + " " + &count.to_string() + " " + pattern;
     }
+    formula_string += ")";
     formula_string
 }
 
-fn get_patterns_formulas(alt_patterns: &HashMap<String, i32>, egraph: &mut EGraph<SimpleLanguage, ()>) {
-    change_alt_patterns_cost(alt_patterns);
-    let num_input_patterns = NUM_PATTERNS.lock().unwrap();
-    unsafe {
-        let patterns = get_global_patterns_vec();
-        for i in 0..*num_input_patterns {
-            let pattern = "(Morph (Pi ".to_string() + &i.to_string() + ") " + &patterns[i] + ")";
-            let new_expr: RecExpr<SimpleLanguage> = pattern.parse().unwrap(); 
-            let new_root = egraph.add_expr(&new_expr);
-            let extractor = Extractor::new(&egraph, NaiveCostFunction);
-            let (best_cost, best) = extractor.find_best(new_root);
-            let best_string = format!("{}", best);
-            let map = simplify_pattern_formula(best_string);
-            let best_string = pattern_formula_to_string(&map);
-            println!();
-            println!("Simplified {} to {}", new_expr, best_string);
+// The value of an extracted formula, with term_value giving the count of every term under
+// the key simplify_pattern_formula uses for it.
+pub fn evaluate_pattern_formula(input: &str, term_value: &mut dyn FnMut(&str) -> i64) -> Result<i64, OptimizerError> {
+    let overflow = || OptimizerError::Evaluation(format!("{} overflows", input));
+    if !input.starts_with('(') {
+        return input.parse().map_err(|_| OptimizerError::Evaluation(format!("unexpected '{}' in a formula", input)));
+    }
+    let (key_word, rest) = input.split_once(' ').unwrap();
+    match key_word {
+        "(Sum" => {
+            let mut value: i64 = 0;
+            for (num, term) in parse_sum_string(rest.to_string())? {
+                let term_count = num.checked_mul(evaluate_pattern_formula(&term, term_value)?).ok_or_else(overflow)?;
+                value = value.checked_add(term_count).ok_or_else(overflow)?;
+            }
+            Ok(value)
+        },
+        "(Count" => {
+            let (num, right) = parse_count_string(rest.to_string())?;
+            num.checked_mul(evaluate_pattern_formula(&right, term_value)?).ok_or_else(overflow)
+        },
+        "(Mul" | "(Sub" | "(Div" => {
+            let operands = parse_operands_string(rest.to_string());
+            let left = evaluate_pattern_formula(&operands[0], term_value)?;
+            let right = evaluate_pattern_formula(&operands[1], term_value)?;
+            match key_word {
+                "(Mul" => left.checked_mul(right).ok_or_else(overflow),
+                "(Sub" => left.checked_sub(right).ok_or_else(overflow),
+                _ if right == 0 || left % right != 0 => Err(OptimizerError::Evaluation(format!("{} is not an integer, {} / {}", input, left, right))),
+                _ => Ok(left / right),
+            }
+        },
+        "(Morph" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            Ok(term_value(&pattern))
+        },
+        "(Hom" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            Ok(term_value(&("(Hom ".to_string() + &pattern + ")")))
+        },
+        "(Complement" => {
+            let (_provenance, pattern) = parse_morph_string(rest.to_string());
+            Ok(term_value(&("(Complement ".to_string() + &pattern + ")")))
+        },
+        "(Local" => {
+            let (_provenance, anchor, pattern) = parse_local_string(rest.to_string());
+            Ok(term_value(&("(Local ".to_string() + &anchor + " " + &pattern + ")")))
+        },
+        "(Join" => Ok(term_value(&get_join_key(rest))),
+        "(Const" => {
+            // 0 is what sum_dedup leaves when every term cancels
+            let (_provenance, formula) = parse_const_string(rest.to_string());
+            if formula == "0" {
+                return Ok(0);
+            }
+            Ok(term_value(&formula))
+        },
+        _ => Err(OptimizerError::Evaluation(format!("can't evaluate {}", input))),
+    }
+}
+
+pub fn get_patterns_formulas(alt_patterns: &HashMap<String, i32>, input_terms: &Vec<String>, egraph: &mut EGraph<SimpleLanguage, Optimizer>, explain: bool) -> Result<Vec<InputFormula>, OptimizerError> {
+    change_alt_patterns_cost(alt_patterns, &mut egraph.analysis.cost_map);
+    let mut formulas = vec![];
+    for i in 0..egraph.analysis.num_patterns {
+        let new_expr: RecExpr<SimpleLanguage> = input_terms[i].parse().unwrap();
+        let new_root = egraph.add_expr(&new_expr);
+        let (best_cost, best) = Extractor::new(egraph, NaiveCostFunction { egraph: egraph }).find_best(new_root);
+        let map = simplify_pattern_formula(format!("{}", best))?;
+        let mut explanation = None;
+        if explain {
+            explanation = Some(get_rewrite_explanation(&new_expr, &best, egraph));
         }
+        formulas.push(InputFormula {
+            input: i,
+            anchor: None,
+            expression: new_expr.to_string(),
+            formula: best.to_string(),
+            terms: map,
+            cost: best_cost,
+            explanation: explanation,
+        });
     }
+    Ok(formulas)
 }
 
-fn print_alt_patterns(patterns: &HashMap<String, i32>, cost: &f64) {
+pub fn print_alt_patterns(patterns: &HashMap<String, i32>, cost: &f64, cost_map: &HashMap<String, f64>) {
     let mut no_dup_cost = 0.0;
     println!();
     println!("Alternative Patterns Set with Cost {}:", cost);
-    unsafe {
-        let cost_map = get_global_map();
-        for (pattern, count) in patterns {
-            println!("{} with number {}", pattern, count);
-            no_dup_cost += cost_map.get(pattern).unwrap_or(&0.0);
-        }
+    for (pattern, count) in patterns {
+        println!("{} with number {}", pattern, count);
+        no_dup_cost += cost_map.get(pattern).unwrap_or(&0.0);
     }
     println!();
     let formatted = format!("{:.5e}", no_dup_cost);
     print!("Correct Cost is {}", formatted);
 }
 
-fn get_edge_set_from_bliss_file(path: String) -> (HashSet<(i32, i32)>, i32) {
-    let file = File::open(&path).unwrap();
-    let mut edge_set = HashSet::new();
-    let reader = BufReader::new(file);
-    let mut num_nodes = 0;
-    for line in reader.lines() {
-        let line = line.unwrap();
-        if line.starts_with("p edge") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            num_nodes = parts[2].parse().unwrap();
-        }
-        if line.starts_with("e") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            let n1: i32 = parts[1].parse().unwrap();
-            let n2: i32 = parts[2].parse().unwrap();
-            edge_set.insert((n1, n2));
-        }
-    }
-    (edge_set, num_nodes)
-} 
-
-fn get_canonical_pattern(index: i32, two_file: bool) -> String {
-    let dir_path = &(DIRECTORY_PATH.to_string() + SRC_DIRECTORY);
-    let path = dir_path.to_string() + "canonical" + &index.to_string() + ".txt";
-    let (edge_set, num_nodes) = get_edge_set_from_bliss_file(path);
-    let mut canonical_pattern = "(Match".to_string();
-    for i in 1..num_nodes+1 {
-        for j in i+1..num_nodes+1 {
-            if edge_set.contains(&(i, j)) || edge_set.contains(&(j, i)) {
-                let first_node =( (i as u8 + 96) as char).to_string();
-                let second_node = ((j as u8 + 96) as char).to_string();
-                canonical_pattern += " (-- ";
-                canonical_pattern += &first_node;
-                canonical_pattern += " ";
-                canonical_pattern += &second_node;
-                canonical_pattern += ")";
-            }
-        }
-    }
-    if two_file {
-        let path = dir_path.to_string() + "canonical" + &index.to_string() + &index.to_string() + ".txt";
-        let (second_edge_set, _) = get_edge_set_from_bliss_file(path);
-        let anti_edge_set: HashSet<(i32, i32)> = second_edge_set.difference(&edge_set).cloned().collect();
-        for i in 1..num_nodes+1 {
-            for j in i+1..num_nodes+1 {
-                if anti_edge_set.contains(&(i, j)) || anti_edge_set.contains(&(j, i)) {
-                    let first_node =( (i as u8 + 96) as char).to_string();
-                    let second_node = ((j as u8 + 96) as char).to_string();
-                    canonical_pattern += " (!- ";
-                    canonical_pattern += &first_node;
-                    canonical_pattern += " ";
-                    canonical_pattern += &second_node;
-                    canonical_pattern += ")";
-                }
-            }
-        }
-    }
-    canonical_pattern += ")";
-    canonical_pattern
-}
-
 fn collect_anti_edges_numbers(pattern: &str) -> HashSet<(usize, usize)> {
     let mut anti_edges_number = HashSet::new();
     let anti_edges = collect_anti_edges(pattern);
@@ -1127,29 +949,24 @@ fn collect_anti_edges_numbers(pattern: &str) -> HashSet<(usize, usize)> {
     anti_edges_number
 } 
 
-fn write_input_patterns_to_file(patterns: &Vec<String>, path: &str) -> Vec<(GraphInfo, HashSet<(usize, usize)>)> {
+fn write_input_patterns_to_file(patterns: &Vec<String>, path: &str) -> Result<Vec<(GraphInfo, HashSet<(usize, usize)>)>, OptimizerError> {
     let mut pattern_infos = vec![];
-    let mut index = 0;
-    for pattern in patterns {
+    for (index, pattern) in patterns.iter().enumerate() {
         let edge_induced_pattern = convert_to_edge_induced_pattern(&pattern.to_string(), false);
-        let num_nodes = get_num_nodes(&pattern);
+        let num_nodes = get_num_nodes(pattern);
         let pattern_info: GraphInfo = parse_input(&edge_induced_pattern, num_nodes);
-        let anti_edges_numbers = collect_anti_edges_numbers(&pattern);
+        let anti_edges_numbers = collect_anti_edges_numbers(pattern);
         let bliss_graph = make_graph_file(&pattern_info, true);
         let file_path = path.to_string() + &index.to_string() + ".txt";
-        write_to_file(bliss_graph, file_path); 
-        index += 1;
+        write_to_file(bliss_graph, file_path)?;
         pattern_infos.push((pattern_info, anti_edges_numbers));
     }
-    pattern_infos
+    Ok(pattern_infos)
 }
 
-fn read_node_permutation_from_file(file_name: &str) -> HashMap<usize, usize> {
+fn read_node_permutation_from_file(file_name: &str) -> Result<HashMap<usize, usize>, OptimizerError> {
     let mut map = HashMap::new();
-    let file = File::open(file_name).unwrap();
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.unwrap();
+    for line in read_lines(file_name)? {
         let mut split = line.split_whitespace();
         if let (Some(key), Some(value)) = (split.next(), split.next()) {
             if let (Ok(key), Ok(value)) = (key.parse::<usize>(), value.parse::<usize>()) {
@@ -1157,7 +974,7 @@ fn read_node_permutation_from_file(file_name: &str) -> HashMap<usize, usize> {
             }
         }
     }
-    map
+    Ok(map)
 }
 
 fn collect_anti_edges(input: &str) -> HashSet<(String, String)> {
@@ -1172,39 +989,6 @@ fn collect_anti_edges(input: &str) -> HashSet<(String, String)> {
     result
 }
 
-fn create_permuted_pattern_for_moprh_rule(pattern_info: &(GraphInfo, HashSet<(usize, usize)>), node_permutation: &HashMap<usize, usize>) -> String {
-    let original_pattern = &pattern_info.0.graph;
-    let anti_edges = &pattern_info.1;
-    let mut permuted_pattern = "(Match".to_string();
-    for i in 0..original_pattern.len() {
-        for j in i+1..original_pattern.len() {
-            if anti_edges.contains(&(node_permutation[&i]+1, node_permutation[&j]+1)) || anti_edges.contains(&(node_permutation[&j]+1, node_permutation[&i]+1)) {
-                permuted_pattern += " (!- ";
-                permuted_pattern += &number_to_alphabet(i+1);
-                permuted_pattern += " ";
-                permuted_pattern += &number_to_alphabet(j+1);
-                permuted_pattern += ")";
-            }
-            else if original_pattern[node_permutation[&i]][node_permutation[&j]] == 1 {
-                permuted_pattern += " (-- ";
-                permuted_pattern += &number_to_alphabet(i+1);
-                permuted_pattern += " ";
-                permuted_pattern += &number_to_alphabet(j+1);
-                permuted_pattern += ")";
-            }
-            else {
-                permuted_pattern += " (!- ";
-                permuted_pattern += &number_to_alphabet(i+1);
-                permuted_pattern += " ";
-                permuted_pattern += &number_to_alphabet(j+1);
-                permuted_pattern += ")";
-            }
-        }
-    }
-    permuted_pattern += ")";
-    permuted_pattern
-}
-
 fn create_permuted_pattern(pattern_info: &(GraphInfo, HashSet<(usize, usize)>), node_permutation: &HashMap<usize, usize>) -> String {
     let original_pattern = &pattern_info.0.graph;
     let anti_edges = &pattern_info.1;
@@ -1232,41 +1016,129 @@ fn create_permuted_pattern(pattern_info: &(GraphInfo, HashSet<(usize, usize)>),
 }
 
 
-fn create_canonical_pattern_from_file(path: &str, pattern_infos: &Vec<(GraphInfo, HashSet<(usize, usize)>)>) -> Vec<String> {
+fn create_canonical_pattern_from_file(path: &str, pattern_infos: &Vec<(GraphInfo, HashSet<(usize, usize)>)>) -> Result<Vec<String>, OptimizerError> {
     let mut canonical_patterns = vec![];
     for i in 0..pattern_infos.len() {
         let file_path = path.to_string() + &i.to_string() + ".txt";
-        let node_permutation = read_node_permutation_from_file(&file_path);
+        let node_permutation = read_node_permutation_from_file(&file_path)?;
+        if node_permutation.len() < pattern_infos[i].0.graph.len() {
+            return Err(OptimizerError::parse(&file_path, node_permutation.len() + 1, 1, "bliss returned an incomplete vertex permutation"));
+        }
         let canonical_pattern = create_permuted_pattern(&pattern_infos[i], &node_permutation);
         canonical_patterns.push(canonical_pattern);
     }
-    canonical_patterns
+    Ok(canonical_patterns)
 }
 
-fn compute_canonical_patterns(patterns: &Vec<String>) -> Vec<String> {
+fn compute_canonical_patterns(patterns: &Vec<String>) -> Result<Vec<String>, OptimizerError> {
     let path = &(DIRECTORY_PATH.to_string() + SRC_DIRECTORY);
-    fs::create_dir(path);
-    let pattern_infos = write_input_patterns_to_file(patterns, path);
-    write_to_pipe(&BLISS_PIPE_NAME, "start".to_string());
-    get_done_signal();
-    let canonical_patterns = create_canonical_pattern_from_file(path, &pattern_infos);
-    fs::remove_dir_all(DIRECTORY_PATH.to_string() + SRC_DIRECTORY);
-    canonical_patterns
-}
-
-fn make_patterns_canonical(patterns: &Vec<String>) -> Vec<String> {
-    let num_patterns = NUM_PATTERNS.lock().unwrap();
-    write_to_pipe(&BLISS_PIPE_NAME, (*num_patterns).to_string());
+    create_directory(path)?;
+    let pattern_infos = write_input_patterns_to_file(patterns, path)?;
+    write_to_pipe(BLISS_PIPE_NAME, "start".to_string())?;
+    get_done_signal()?;
+    let canonical_patterns = create_canonical_pattern_from_file(path, &pattern_infos)?;
+    fs::remove_dir_all(path).map_err(|error| OptimizerError::io(path, error))?;
+    Ok(canonical_patterns)
+}
+
+pub fn make_patterns_canonical(patterns: &Vec<String>) -> Result<Vec<String>, OptimizerError> {
+    write_to_pipe(BLISS_PIPE_NAME, patterns.len().to_string())?;
     compute_canonical_patterns(patterns)
 }
 
-fn read_input_patterns_from_file(filename: &str) -> Vec<String> {
-    let input = File::open(filename).unwrap();
-    let reader = BufReader::new(input);
+// Asks bliss for every connected k-vertex graph and turns each one into an input pattern,
+// with anti-edges on all non-edges when vertex-induced counts are wanted.
+pub fn generate_census_patterns(num_nodes: usize, vertex_induced: bool) -> Result<Vec<String>, OptimizerError> {
+    write_to_pipe(BLISS_PIPE_NAME, "census ".to_string() + &num_nodes.to_string())?;
+    get_done_signal()?;
+    let census_directory = DIRECTORY_PATH.to_string() + CENSUS_DIRECTORY;
+    let num_graphs = fs::read_dir(&census_directory).map_err(|error| OptimizerError::io(&census_directory, error))?.count();
     let mut patterns = vec![];
-    for line in reader.lines() {
-        let line = line.unwrap();
-        patterns.push(line);
+    for i in 0..num_graphs {
+        let path = census_directory.to_string() + &i.to_string() + ".txt";
+        let file = File::open(&path).map_err(|error| OptimizerError::io(&path, error))?;
+        let mut reader = BufReader::new(file);
+        let mut coefficient = 0;
+        let graph_info = read_graph_from_bliss_file(&mut reader, &path, &mut coefficient)?;
+        if graph_info.graph.len() != num_nodes {
+            return Err(OptimizerError::parse(&path, 1, 1, &format!("expected a graph on {} vertices", num_nodes)));
+        }
+        let non_edge_label = if vertex_induced { ANTI_EDGE_LABEL } else { NO_LABEL };
+        let mut matrix = vec![vec![non_edge_label; num_nodes]; num_nodes];
+        for u in 0..num_nodes {
+            for v in 0..num_nodes {
+                if graph_info.graph[u][v] == 1 {
+                    matrix[u][v] = EDGE_LABEL;
+                }
+            }
+        }
+        patterns.push(label_matrix_to_pattern(&matrix));
     }
-    patterns
+    fs::remove_dir_all(&census_directory).map_err(|error| OptimizerError::io(&census_directory, error))?;
+    Ok(patterns)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountingSemantics {
+    Embeddings,
+    Subgraphs,
+    Homomorphisms,
+}
+
+// An input line is a Match pattern, optionally prefixed with `Embeddings`, `Subgraphs` or
+// `Homomorphisms`. Without a prefix the pattern counts unique subgraphs, which is what the
+// morph and escape coefficients are written for. Homomorphisms count every map that keeps
+// the labels, distinct only on edges and on the pairs marked <>. Blank lines and lines
+// starting with # are skipped, every other line has to pass validate_pattern.
+pub fn read_input_patterns_from_file(filename: &str) -> Result<(Vec<String>, Vec<CountingSemantics>), OptimizerError> {
+    let mut patterns = vec![];
+    let mut semantics = vec![];
+    for (index, line) in read_lines(filename)?.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let (counting_semantics, pattern) = split_counting_semantics(trimmed);
+        let start = line.trim_end().len() - pattern.len();
+        patterns.push(validate_pattern(line, start, filename, index + 1, counting_semantics == CountingSemantics::Homomorphisms)?);
+        semantics.push(counting_semantics);
+    }
+    if patterns.is_empty() {
+        return Err(OptimizerError::parse(filename, 1, 1, "no patterns"));
+    }
+    Ok((patterns, semantics))
+}
+
+fn split_counting_semantics(line: &str) -> (CountingSemantics, String) {
+    if let Some(pattern) = line.strip_prefix("Embeddings ") {
+        return (CountingSemantics::Embeddings, pattern.trim().to_string());
+    }
+    if let Some(pattern) = line.strip_prefix("Subgraphs ") {
+        return (CountingSemantics::Subgraphs, pattern.trim().to_string());
+    }
+    if let Some(pattern) = line.strip_prefix("Homomorphisms ") {
+        return (CountingSemantics::Homomorphisms, pattern.trim().to_string());
+    }
+    (CountingSemantics::Subgraphs, line.to_string())
+}
+
+// Every plan counts unique subgraphs, embeddings are |Aut(P)| times as many. The group
+// size is folded into the root Count so count_mult pushes it down into every coefficient.
+pub fn get_input_coefficients(patterns: &Vec<String>, semantics: &Vec<CountingSemantics>) -> Result<Vec<i64>, OptimizerError> {
+    let mut coefficients = vec![];
+    for i in 0..patterns.len() {
+        let coefficient = match semantics[i] {
+            CountingSemantics::Embeddings => find_labelled_group_size(&patterns[i], None),
+            CountingSemantics::Subgraphs | CountingSemantics::Homomorphisms => 1,
+        };
+        coefficients.push(coefficient);
+    }
+    Ok(coefficients)
+}
+
+// The automorphisms that keep every pair's label (those fixing the anchor, when there is
+// one), so partially induced and anchored patterns keep anti-edges and free pairs apart.
+pub fn find_labelled_group_size(pattern: &str, anchor: Option<usize>) -> i64 {
+    let (_, group_size) = canonical_label_matrix(&pattern_to_label_matrix(pattern), anchor);
+    group_size as i64
 }
@@ -0,0 +1,226 @@
+use crate::*;
+use std::collections::HashMap;
+
+
+// Input lines are checked against
+//   (Match (op u v) ...)   op: -- edge, !- anti-edge, <> distinct
+// with vertices named a, b, c, ... and reported with their column before anything reaches
+// bliss or the e-graph, which only see patterns that passed. Homomorphism counts keep
+// their <> atoms, they are the only pairs besides edges that have to stay distinct.
+pub fn validate_pattern(line: &str, start: usize, path: &str, line_number: usize, keep_not_equal: bool) -> Result<String, OptimizerError> {
+    let tokens = tokenize_pattern(line, start);
+    let end_column = line.trim_end().len() + 1;
+    let mut position = 0;
+    let mut next_token = |expected: &str| -> Result<(usize, &str), OptimizerError> {
+        position += 1;
+        match tokens.get(position - 1) {
+            Some(&token) => Ok(token),
+            None => Err(OptimizerError::parse(path, line_number, end_column, &format!("unexpected end of line, expected {}", expected))),
+        }
+    };
+    let unexpected = |column: usize, token: &str, expected: &str| {
+        OptimizerError::parse(path, line_number, column, &format!("unexpected '{}', expected {}", token, expected))
+    };
+
+    let (column, token) = next_token("'(Match'")?;
+    if token != "(" {
+        return Err(unexpected(column, token, "'(Match'"));
+    }
+    let (match_column, token) = next_token("'Match'")?;
+    if token != "Match" {
+        return Err(unexpected(match_column, token, "'Match'"));
+    }
+    let mut atoms: Vec<(&str, &str, &str, usize)> = vec![];
+    loop {
+        let (column, token) = next_token("an atom or ')'")?;
+        if token == ")" {
+            break;
+        }
+        if token != "(" {
+            return Err(unexpected(column, token, "an atom or ')'"));
+        }
+        let (op_column, op) = next_token("'--', '!-' or '<>'")?;
+        if op != "--" && op != "!-" && op != "<>" {
+            return Err(unexpected(op_column, op, "'--', '!-' or '<>'"));
+        }
+        let mut vertices = vec![];
+        for _ in 0..2 {
+            let (column, vertex) = next_token("a vertex")?;
+            if vertex.is_empty() || !vertex.chars().all(|c| c.is_ascii_lowercase()) {
+                return Err(unexpected(column, vertex, "a vertex (a, b, c, ...)"));
+            }
+            vertices.push((column, vertex));
+        }
+        let (column, token) = next_token("')'")?;
+        if token != ")" {
+            return Err(unexpected(column, token, "')'"));
+        }
+        if vertices[0].1 == vertices[1].1 {
+            return Err(OptimizerError::parse(path, line_number, vertices[1].0, &format!("self-loop on '{}'", vertices[0].1)));
+        }
+        atoms.push((op, vertices[0].1, vertices[1].1, op_column - 1));
+    }
+    if let Some(&(column, token)) = tokens.get(position) {
+        return Err(OptimizerError::parse(path, line_number, column, &format!("unexpected '{}' after the pattern", token)));
+    }
+    if atoms.is_empty() {
+        return Err(OptimizerError::parse(path, line_number, match_column, "empty pattern"));
+    }
+    check_pattern_pairs(&atoms, path, line_number)?;
+    check_pattern_vertices(&atoms, path, line_number)?;
+    if keep_not_equal {
+        check_not_equal_pairs(&atoms, path, line_number)?;
+    }
+
+    // Otherwise every Match vertex is matched injectively, a <> only restates that.
+    let mut pattern = "(Match".to_string();
+    for &(op, u, v, _) in atoms.iter().filter(|atom| keep_not_equal || atom.0 != "<>") {
+        pattern += &format!(" ({} {} {})", op, u, v);
+    }
+    pattern += ")";
+    Ok(pattern)
+}
+
+// Parentheses and whitespace separated words from line[start..], with 1-based columns.
+fn tokenize_pattern(line: &str, start: usize) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut word_start = None;
+    for (index, c) in line[start..].char_indices() {
+        let index = start + index;
+        if c == '(' || c == ')' || c.is_whitespace() {
+            if let Some(word_start) = word_start.take() {
+                tokens.push((word_start + 1, &line[word_start..index]));
+            }
+            if !c.is_whitespace() {
+                tokens.push((index + 1, &line[index..index + 1]));
+            }
+        }
+        else if word_start.is_none() {
+            word_start = Some(index);
+        }
+    }
+    if let Some(word_start) = word_start {
+        tokens.push((word_start + 1, &line[word_start..]));
+    }
+    tokens
+}
+
+// A pair of vertices takes at most one label, either way round.
+fn check_pattern_pairs(atoms: &Vec<(&str, &str, &str, usize)>, path: &str, line_number: usize) -> Result<(), OptimizerError> {
+    let mut pairs: HashMap<(&str, &str, bool), (&str, usize)> = HashMap::new();
+    for &(op, u, v, column) in atoms.iter() {
+        let pair = if u < v { (u, v) } else { (v, u) };
+        let key = (pair.0, pair.1, op == "<>");
+        match pairs.get(&key) {
+            Some(&(first_op, first_column)) if first_op == op => {
+                return Err(OptimizerError::parse(path, line_number, column, &format!("duplicate ({} {} {}), already given at column {}", op, u, v, first_column)));
+            }
+            Some(&(first_op, first_column)) => {
+                return Err(OptimizerError::parse(path, line_number, column, &format!("({} {} {}) conflicts with ({} {} {}) at column {}", op, u, v, first_op, pair.0, pair.1, first_column)));
+            }
+            None => {
+                pairs.insert(key, (op, column));
+            }
+        }
+    }
+    Ok(())
+}
+
+// The rest of the pipeline indexes vertices by name, so the names have to be the first n
+// of a, b, c, .... Disconnected patterns are counted from their components, but every
+// vertex has to keep an edge or anti-edge once the <> atoms are dropped.
+fn check_pattern_vertices(atoms: &Vec<(&str, &str, &str, usize)>, path: &str, line_number: usize) -> Result<(), OptimizerError> {
+    let mut first_columns: HashMap<usize, usize> = HashMap::new();
+    for &(_, u, v, column) in atoms.iter() {
+        for vertex in [u, v] {
+            first_columns.entry(alphabet_to_number(vertex)).or_insert(column);
+        }
+    }
+    let num_nodes = first_columns.len();
+    for number in 1..=num_nodes {
+        if !first_columns.contains_key(&number) {
+            let (&unexpected, &column) = first_columns.iter().filter(|&(&other, _)| other > num_nodes).min().unwrap();
+            return Err(OptimizerError::parse(path, line_number, column, &format!("vertices have to be named a, b, c, ... without gaps, '{}' is used but '{}' is missing", number_to_alphabet(unexpected), number_to_alphabet(number))));
+        }
+    }
+
+    let mut labelled = vec![false; num_nodes];
+    for &(_, u, v, _) in atoms.iter().filter(|atom| atom.0 != "<>") {
+        labelled[alphabet_to_number(u) - 1] = true;
+        labelled[alphabet_to_number(v) - 1] = true;
+    }
+    if let Some(node) = (0..num_nodes).find(|&node| !labelled[node]) {
+        return Err(OptimizerError::parse(path, line_number, first_columns[&(node + 1)], &format!("'{}' only appears in <> atoms, it needs an edge or anti-edge", number_to_alphabet(node + 1))));
+    }
+    Ok(())
+}
+
+// A pair has one label in the label matrix, so a <> can only go on a pair without an edge
+// or anti-edge. An edge already keeps its ends apart.
+fn check_not_equal_pairs(atoms: &Vec<(&str, &str, &str, usize)>, path: &str, line_number: usize) -> Result<(), OptimizerError> {
+    for &(_op, u, v, column) in atoms.iter().filter(|atom| atom.0 == "<>") {
+        let labelled = atoms.iter().find(|atom| atom.0 != "<>" && ((atom.1, atom.2) == (u, v) || (atom.1, atom.2) == (v, u)));
+        if let Some(&(other_op, _, _, other_column)) = labelled {
+            return Err(OptimizerError::parse(path, line_number, column, &format!("(<> {} {}) on a pair that already has ({} {} {}) at column {}", u, v, other_op, u, v, other_column)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The column and message a pattern is rejected with.
+    fn rejection(line: &str, keep_not_equal: bool) -> (usize, String) {
+        match validate_pattern(line, 0, "patterns", 1, keep_not_equal) {
+            Err(OptimizerError::Parse { line: 1, column, message, .. }) => (column, message),
+            other => panic!("{}: expected a parse error, got {:?}", line, other),
+        }
+    }
+
+    fn assert_rejected(line: &str, keep_not_equal: bool, column: usize, message: &str) {
+        let (actual_column, actual_message) = rejection(line, keep_not_equal);
+        assert_eq!(actual_column, column, "{}: {}", line, actual_message);
+        assert!(actual_message.contains(message), "{}: {}", line, actual_message);
+    }
+
+    #[test]
+    fn valid_patterns_are_normalized() {
+        assert_eq!(validate_pattern("  (Match (-- a b)(!- b c) (<> a c))", 2, "patterns", 1, false).unwrap(), "(Match (-- a b) (!- b c))");
+        assert_eq!(validate_pattern("(Match (-- a b) (-- b c) (<> a c))", 0, "patterns", 1, true).unwrap(), "(Match (-- a b) (-- b c) (<> a c))");
+    }
+
+    #[test]
+    fn malformed_syntax_is_reported_at_its_column() {
+        assert_rejected("Match (-- a b))", false, 1, "unexpected 'Match', expected '(Match'");
+        assert_rejected("(Count (-- a b))", false, 2, "expected 'Match'");
+        assert_rejected("(Match -- a b)", false, 8, "expected an atom or ')'");
+        assert_rejected("(Match (-> a b))", false, 9, "expected '--', '!-' or '<>'");
+        assert_rejected("(Match (-- a b c))", false, 16, "expected ')'");
+        assert_rejected("(Match (-- a b)) (-- b c)", false, 18, "after the pattern");
+        assert_rejected("(Match (-- a b)", false, 16, "unexpected end of line");
+        assert_rejected("(Match)", false, 2, "empty pattern");
+    }
+
+    #[test]
+    fn bad_vertex_names_are_rejected() {
+        assert_rejected("(Match (-- a B))", false, 14, "expected a vertex (a, b, c, ...)");
+        assert_rejected("(Match (-- a 2))", false, 14, "expected a vertex (a, b, c, ...)");
+        assert_rejected("(Match (-- a a))", false, 14, "self-loop on 'a'");
+        assert_rejected("(Match (-- a b) (-- b d))", false, 17, "'d' is used but 'c' is missing");
+    }
+
+    #[test]
+    fn conflicting_pairs_are_rejected() {
+        assert_rejected("(Match (-- a b) (-- b a))", false, 17, "duplicate (-- b a), already given at column 8");
+        assert_rejected("(Match (-- a b) (!- b a))", false, 17, "(!- b a) conflicts with (-- a b) at column 8");
+    }
+
+    #[test]
+    fn not_equal_misuse_is_rejected() {
+        assert_rejected("(Match (-- a b) (<> b c))", false, 17, "'c' only appears in <> atoms");
+        assert_rejected("(Match (-- a b) (<> a b))", true, 17, "(<> a b) on a pair that already has (-- a b) at column 8");
+        assert_rejected("(Match (-- a b) (<> a b) (<> b a))", true, 26, "duplicate (<> b a), already given at column 17");
+    }
+}